@@ -0,0 +1,112 @@
+use std::fmt::Write;
+
+use super::Instruction;
+use crate::constants;
+
+pub const EXECUTED: u8 = 0b001;
+pub const READ: u8 = 0b010;
+pub const WRITTEN: u8 = 0b100;
+
+/// Map symbols, indexed by the coverage flags of a byte
+const SYMBOLS: [char; 8] = ['.', 'X', 'R', 'B', 'W', 'M', 'D', 'A'];
+
+///
+/// Coverage
+///
+/// Tracks, per RAM byte, whether it was executed as an opcode, read as data or written.
+///
+#[derive(Debug)]
+#[must_use]
+pub struct Coverage {
+    flags: [u8; constants::SYSTEM_RAM as usize],
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self {
+            flags: [0; constants::SYSTEM_RAM as usize],
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, addr: u16) -> u8 {
+        self.flags[addr as usize % self.flags.len()]
+    }
+
+    #[inline]
+    pub fn mark(&mut self, addr: u16, len: u16, flag: u8) {
+        for offset in 0..len {
+            let addr = addr.wrapping_add(offset) as usize % self.flags.len();
+            self.flags[addr] |= flag;
+        }
+    }
+
+    ///
+    /// Returns the whole map, one line per 64 bytes of RAM
+    ///
+    pub fn to_map(&self) -> String {
+        let mut map = String::new();
+        writeln!(map, "# CHIP-8 coverage map").unwrap();
+        writeln!(
+            map,
+            "# . untouched, X executed, R read, W written, B executed+read, M executed+written, D read+written, A all"
+        )
+        .unwrap();
+
+        for (row, chunk) in self.flags.chunks(64).enumerate() {
+            let symbols: String = chunk.iter().map(|flags| SYMBOLS[*flags as usize]).collect();
+            writeln!(map, "{:03X}: {}", row * 64, symbols).unwrap();
+        }
+
+        map
+    }
+
+    ///
+    /// Returns a disassembly of the ROM with the coverage of each byte in the margin
+    ///
+    /// Executed and untouched words are disassembled as instructions, so untested code paths show up as code
+    /// without an `X`. Bytes read or written as data are listed one per line along with their bit pattern.
+    ///
+    pub fn to_disassembly(&self, rom: &[u8]) -> String {
+        let mut listing = String::new();
+        let mut offset = 0;
+
+        while offset < rom.len() {
+            let addr = constants::PROGRAM_START_ADDR + offset as u16;
+            let flags = self.get(addr);
+            let next_flags = self.get(addr + 1);
+
+            let is_code = flags & EXECUTED != 0 || (flags == 0 && next_flags == 0);
+            if is_code && offset + 1 < rom.len() {
+                let word = (rom[offset] as u16) << 8 | rom[offset + 1] as u16;
+                writeln!(
+                    listing,
+                    "{:03X}  {:04X}  {}{}  {}",
+                    addr,
+                    word,
+                    SYMBOLS[flags as usize],
+                    SYMBOLS[next_flags as usize],
+                    Instruction::decode(word)
+                )
+                .unwrap();
+                offset += 2;
+            } else {
+                writeln!(
+                    listing,
+                    "{:03X}  {:02X}    {}   DB 0x{:02X}  ; {}",
+                    addr,
+                    rom[offset],
+                    SYMBOLS[flags as usize],
+                    rom[offset],
+                    format!("{:08b}", rom[offset])
+                        .replace('0', ".")
+                        .replace('1', "#")
+                )
+                .unwrap();
+                offset += 1;
+            }
+        }
+
+        listing
+    }
+}
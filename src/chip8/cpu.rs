@@ -1,20 +1,47 @@
+mod block_cache;
 mod input_buffer;
 mod program_counter;
 mod registers;
 mod stack;
 
-use input_buffer::InputBuffer;
+use block_cache::BlockCache;
+pub use input_buffer::{InputBuffer, KeyEvent};
 use log::debug;
 use program_counter::ProgramCounter;
 use rand::{rngs::ThreadRng, Rng};
 use registers::Registers;
 use stack::Stack;
 
-use super::{Memory, VideoMemory};
+use super::{coverage, Coverage, Instruction, Memory, Profiler, TraceWriter, VideoMemory};
 
-use crate::{constants, fonts::CHIP8_FONTS, utils::read_bit_from_byte};
+use crate::{constants, fonts::CHIP8_FONTS};
 
-pub type Opcode = (u8, u8, u8, u8);
+///
+/// Native code produced by the `recompile` tool. Runs the block at the program counter if it fits in the given
+/// number of ticks and returns the number of instructions executed, or 0 to let the interpreter take over.
+///
+pub type RecompiledCode = fn(&mut Cpu, usize) -> usize;
+
+///
+/// When `Fx0A` completes
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyWait {
+    /// As soon as a key is pressed
+    Press,
+    /// When the key pressed is released, as on the COSMAC VIP
+    Release,
+}
+
+impl KeyWait {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "press" => Some(Self::Press),
+            "release" => Some(Self::Release),
+            _ => None,
+        }
+    }
+}
 
 ///
 /// CPU
@@ -23,11 +50,9 @@ pub type Opcode = (u8, u8, u8, u8);
 pub struct Cpu {
     pub quit_flag: bool,
     pub schip_mode: bool,
-    pub hires_mode: bool,
     pub cycle: usize,
     ram: Memory,
     pub vram: VideoMemory,
-    pub vram_changed: bool,
     v: Registers,
     i: u16,
     pc: ProgramCounter,
@@ -38,6 +63,15 @@ pub struct Cpu {
     pub keys: InputBuffer,
     key_register: u8,
     waiting_for_key: bool,
+    pub key_wait: KeyWait,
+    /// Key pressed during `Fx0A`, when waiting for its release
+    key_held: Option<u8>,
+    pub coverage: Option<Coverage>,
+    pub profiler: Option<Profiler>,
+    pub trace: Option<TraceWriter>,
+    blocks: BlockCache,
+    code_modified: bool,
+    pub recompiled: Option<RecompiledCode>,
 }
 
 impl Cpu {
@@ -45,11 +79,9 @@ impl Cpu {
         let mut cpu = Self {
             quit_flag: false,
             schip_mode,
-            hires_mode: false,
             cycle: 0,
             ram: Memory::new(),
             vram: VideoMemory::new(),
-            vram_changed: false,
             v: Registers::new(),
             i: 0,
             pc: ProgramCounter::new(),
@@ -60,6 +92,14 @@ impl Cpu {
             keys: InputBuffer::new(),
             key_register: 0,
             waiting_for_key: false,
+            key_wait: KeyWait::Release,
+            key_held: None,
+            coverage: None,
+            profiler: None,
+            trace: None,
+            blocks: BlockCache::new(),
+            code_modified: false,
+            recompiled: None,
         };
 
         let mut i = constants::FONT_START_ADDR;
@@ -82,6 +122,20 @@ impl Cpu {
         self.delay_timer = 0;
         self.sound_timer = 0;
         self.keys.clear();
+        self.waiting_for_key = false;
+        self.key_held = None;
+        self.blocks.clear();
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.reset_call_stack();
+        }
+    }
+
+    ///
+    /// Returns the largest program that fits in the variant's memory, from the start of the program to the end of
+    /// RAM. CHIP-8 and S-CHIP both get 0xE00 bytes here, since the stack and the screen live outside of RAM.
+    ///
+    pub fn max_program_size(&self) -> usize {
+        self.ram.size() - constants::PROGRAM_START_ADDR as usize
     }
 
     #[inline]
@@ -91,37 +145,175 @@ impl Cpu {
             self.ram.write(address, *v);
             address += 1;
         }
+        self.blocks.clear();
     }
 
+    ///
+    /// Executes a single instruction
+    ///
     #[inline]
     pub fn tick(&mut self) {
-        self.cycle = self.cycle.wrapping_add(1);
+        self.step();
+    }
+
+    ///
+    /// Executes up to `max_ticks` instructions from the block cache. Returns the number of instructions executed,
+    /// which is lower than the number of ticks if the CPU spent some of them waiting for a key.
+    ///
+    /// Recompiled code takes precedence over the block cache when there is some for the program counter. Runs
+    /// with coverage, profiling or tracing enabled fall back to stepping through instructions one by one.
+    ///
+    pub fn run(&mut self, max_ticks: usize) -> usize {
+        let mut ticks = 0;
+        let mut executed = 0;
+        while ticks < max_ticks && !self.quit_flag {
+            if self.waiting_for_key
+                || self.coverage.is_some()
+                || self.profiler.is_some()
+                || self.trace.is_some()
+            {
+                executed += self.step() as usize;
+                ticks += 1;
+                continue;
+            }
+
+            if let Some(run_block) = self.recompiled {
+                let count = run_block(self, max_ticks - ticks);
+                if count > 0 {
+                    ticks += count;
+                    executed += count;
+                    continue;
+                }
+            }
 
-        self.vram_changed = false;
+            let block = self.blocks.fetch(self.pc.address, &self.ram);
+            if block.instructions.is_empty() {
+                executed += self.step() as usize;
+                ticks += 1;
+                continue;
+            }
+
+            for instruction in &block.instructions {
+                self.begin_instruction();
+                self.execute(*instruction);
+
+                ticks += 1;
+                executed += 1;
+                if ticks == max_ticks || self.code_modified || self.waiting_for_key {
+                    break;
+                }
+            }
+            self.code_modified = false;
+        }
+
+        executed
+    }
+
+    ///
+    /// Fetches, decodes and executes the instruction at the program counter. Returns false if the CPU is waiting
+    /// for a key instead.
+    ///
+    fn step(&mut self) -> bool {
+        self.cycle = self.cycle.wrapping_add(1);
 
         if self.waiting_for_key {
-            for key in 0..15 as usize {
-                if *self.keys.get_key(key) {
+            match self.poll_key_wait() {
+                Some(key) => {
                     self.waiting_for_key = false;
-                    self.v.write(self.key_register, key as u8);
+                    self.v.write(self.key_register, key);
                     self.key_register = 0;
                 }
+                None => {
+                    if let Some(profiler) = self.profiler.as_mut() {
+                        profiler.record_key_wait();
+                    }
+                    return false;
+                }
+            }
+        }
+
+        let instruction = Instruction::decode(self.read_instruction());
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.mark(self.pc.address, constants::OPCODE_SIZE, coverage::EXECUTED);
+        }
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(self.pc.address, instruction, self.delay_timer);
+        }
+        if let Some(trace) = self.trace.as_mut() {
+            trace.record(self.pc.address);
+        }
+        self.pc.next();
+        self.execute(instruction);
+        self.code_modified = false;
+
+        true
+    }
+
+    ///
+    /// Returns the key that completes `Fx0A`, if there's one yet
+    ///
+    fn poll_key_wait(&mut self) -> Option<u8> {
+        match (self.key_wait, self.key_held) {
+            (KeyWait::Press, _) => self.keys.take_press(),
+            (KeyWait::Release, None) => {
+                self.key_held = self.keys.take_press();
+                // A tap can be pressed and released between two checks
+                self.key_held
+                    .filter(|&key| !self.keys.get_key(key as usize))
             }
-            if !self.waiting_for_key {
-                return;
+            (KeyWait::Release, Some(key)) => {
+                if *self.keys.get_key(key as usize) {
+                    None
+                } else {
+                    Some(key)
+                }
             }
         }
+        .inspect(|_| self.key_held = None)
+    }
+
+    ///
+    /// Does the bookkeeping that precedes every instruction: counts the tick and moves the program counter past
+    /// the instruction
+    ///
+    #[inline]
+    pub fn begin_instruction(&mut self) {
+        self.cycle = self.cycle.wrapping_add(1);
+        self.pc.next();
+    }
+
+    ///
+    /// Returns true if RAM at the address still holds the given code
+    ///
+    #[inline]
+    #[cfg(feature = "recompiled")]
+    pub fn code_matches(&self, address: u16, code: &[u8]) -> bool {
+        self.ram.read_slice(address, code.len() as u16) == code
+    }
 
+    ///
+    /// Counts the delay and sound timers down, once per 60 Hz frame whatever the clock. They keep running while
+    /// the CPU waits for a key.
+    ///
+    #[inline]
+    pub fn tick_timers(&mut self) {
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
         if self.sound_timer > 0 {
             self.sound_timer -= 1;
         }
+    }
 
-        let opcode = self.read_instruction();
-        self.pc.next();
-        self.execute_instruction(opcode);
+    #[inline]
+    #[cfg(feature = "recompiled")]
+    pub fn get_program_counter(&self) -> u16 {
+        self.pc.address
+    }
+
+    #[inline]
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.waiting_for_key
     }
 
     #[inline]
@@ -129,66 +321,68 @@ impl Cpu {
         (*self.ram.read(self.pc.address) as u16) << 8 | (*self.ram.read(self.pc.address + 1) as u16)
     }
 
-    fn execute_instruction(&mut self, instruction: u16) {
-        let opcode: Opcode = (
-            ((instruction & 0xF000) >> 12) as u8,
-            ((instruction & 0x0F00) >> 8) as u8,
-            ((instruction & 0x00F0) >> 4) as u8,
-            (instruction & 0x000F) as u8,
-        );
+    #[inline]
+    pub fn read_memory(&self, address: u16) -> u8 {
+        *self.ram.read(address)
+    }
+
+    ///
+    /// Writes to RAM, flushing the block cache if the address holds decoded code
+    ///
+    #[inline]
+    fn write_memory(&mut self, address: u16, value: u8) {
+        self.ram.write(address, value);
+        if self.blocks.invalidate(address) {
+            self.code_modified = true;
+        }
+    }
 
-        let x = opcode.1.try_into().unwrap();
-        let y = opcode.2.try_into().unwrap();
-
-        let n: u8 = opcode.3.try_into().unwrap();
-        let kk = (instruction & 0x00FF) as u8;
-        let nnn = instruction & 0x0FFF;
-
-        match opcode {
-            (0x0, 0x0, 0xC, _) => self.scd(n), // SCHIP only
-            (0x0, 0x0, 0xE, 0x0) => self.cls(),
-            (0x0, 0x0, 0xE, 0xE) => self.ret(),
-            (0x0, 0x0, 0xF, 0xB) => self.scr(),  // SCHIP only
-            (0x0, 0x0, 0xF, 0xC) => self.scl(),  // SCHIP only
-            (0x0, 0x0, 0xF, 0xD) => self.exit(), // SCHIP only
-            (0x0, 0x0, 0xF, 0xE) => self.low(),  // SCHIP only
-            (0x0, 0x0, 0xF, 0xF) => self.high(), // SCHIP only
-            (0x1, _, _, _) => self.jp(nnn),
-            (0x2, _, _, _) => self.call(nnn),
-            (0x3, _, _, _) => self.se_vx(x, kk),
-            (0x4, _, _, _) => self.sne_vx(x, kk),
-            (0x5, _, _, 0x0) => self.se_vx_vy(x, y),
-            (0x6, _, _, _) => self.ld_vx(x, kk),
-            (0x7, _, _, _) => self.add_vx(x, kk),
-            (0x8, _, _, 0x0) => self.ld_vx_vy(x, y),
-            (0x8, _, _, 0x1) => self.or_vx_vy(x, y),
-            (0x8, _, _, 0x2) => self.and_vx_vy(x, y),
-            (0x8, _, _, 0x3) => self.xor_vx_vy(x, y),
-            (0x8, _, _, 0x4) => self.add_vx_vy(x, y),
-            (0x8, _, _, 0x5) => self.sub_vx_vy(x, y),
-            (0x8, _, _, 0x6) => self.shr_vx_vy(x, y), // SCHIP behavior
-            (0x8, _, _, 0x7) => self.subn_vx_vy(x, y),
-            (0x8, _, _, 0xE) => self.shl_vx_vy(x, y), // SCHIP behavior
-            (0x9, _, _, 0x0) => self.sne_vx_vy(x, y),
-            (0xA, _, _, _) => self.ld_i(nnn),
-            (0xB, _, _, _) => self.jp_v0(nnn),
-            (0xC, _, _, _) => self.rnd_vx(x, kk),
-            (0xD, _, _, _) => self.drw_vx_vy(x, y, n), // SCHIP behavior
-            (0xE, _, 0x9, 0xE) => self.skp_vx(x),
-            (0xE, _, 0xA, 0x1) => self.sknp_vx(x),
-            (0xF, _, 0x0, 0x7) => self.ld_vx_dt(x),
-            (0xF, _, 0x0, 0xA) => self.ld_vx_k(x),
-            (0xF, _, 0x1, 0x5) => self.ld_dt_vx(x),
-            (0xF, _, 0x1, 0x8) => self.ld_st_vx(x),
-            (0xF, _, 0x1, 0xE) => self.add_i_vx(x),
-            (0xF, _, 0x2, 0x9) => self.ld_f_vx(x),
-            (0xF, _, 0x3, 0x0) => self.ld_hf_vx(x), // SCHIP only
-            (0xF, _, 0x3, 0x3) => self.ld_b_vx(x),
-            (0xF, _, 0x5, 0x5) => self.ld_i_vx(x), // SCHIP behavior
-            (0xF, _, 0x6, 0x5) => self.ld_vx_i(x),
-            (0xF, _, 0x7, 0x5) => self.ld_r_vx(x), // SCHIP only
-            (0xF, _, 0x8, 0x5) => self.ld_vx_r(x), // SCHIP only
-            _ => self.no_op(opcode),
+    fn execute(&mut self, instruction: Instruction) {
+        match instruction {
+            Instruction::Scd(n) => self.scd(n), // SCHIP only
+            Instruction::Cls => self.cls(),
+            Instruction::Ret => self.ret(),
+            Instruction::Scr => self.scr(),   // SCHIP only
+            Instruction::Scl => self.scl(),   // SCHIP only
+            Instruction::Exit => self.exit(), // SCHIP only
+            Instruction::Low => self.low(),   // SCHIP only
+            Instruction::High => self.high(), // SCHIP only
+            Instruction::Jp(nnn) => self.jp(nnn),
+            Instruction::Call(nnn) => self.call(nnn),
+            Instruction::SeVx(x, kk) => self.se_vx(x, kk),
+            Instruction::SneVx(x, kk) => self.sne_vx(x, kk),
+            Instruction::SeVxVy(x, y) => self.se_vx_vy(x, y),
+            Instruction::LdVx(x, kk) => self.ld_vx(x, kk),
+            Instruction::AddVx(x, kk) => self.add_vx(x, kk),
+            Instruction::LdVxVy(x, y) => self.ld_vx_vy(x, y),
+            Instruction::OrVxVy(x, y) => self.or_vx_vy(x, y),
+            Instruction::AndVxVy(x, y) => self.and_vx_vy(x, y),
+            Instruction::XorVxVy(x, y) => self.xor_vx_vy(x, y),
+            Instruction::AddVxVy(x, y) => self.add_vx_vy(x, y),
+            Instruction::SubVxVy(x, y) => self.sub_vx_vy(x, y),
+            Instruction::ShrVxVy(x, y) => self.shr_vx_vy(x, y), // SCHIP behavior
+            Instruction::SubnVxVy(x, y) => self.subn_vx_vy(x, y),
+            Instruction::ShlVxVy(x, y) => self.shl_vx_vy(x, y), // SCHIP behavior
+            Instruction::SneVxVy(x, y) => self.sne_vx_vy(x, y),
+            Instruction::LdI(nnn) => self.ld_i(nnn),
+            Instruction::JpV0(nnn) => self.jp_v0(nnn),
+            Instruction::RndVx(x, kk) => self.rnd_vx(x, kk),
+            Instruction::DrwVxVy(x, y, n) => self.drw_vx_vy(x, y, n), // SCHIP behavior
+            Instruction::SkpVx(x) => self.skp_vx(x),
+            Instruction::SknpVx(x) => self.sknp_vx(x),
+            Instruction::LdVxDt(x) => self.ld_vx_dt(x),
+            Instruction::LdVxK(x) => self.ld_vx_k(x),
+            Instruction::LdDtVx(x) => self.ld_dt_vx(x),
+            Instruction::LdStVx(x) => self.ld_st_vx(x),
+            Instruction::AddIVx(x) => self.add_i_vx(x),
+            Instruction::LdFVx(x) => self.ld_f_vx(x),
+            Instruction::LdHfVx(x) => self.ld_hf_vx(x), // SCHIP only
+            Instruction::LdBVx(x) => self.ld_b_vx(x),
+            Instruction::LdIVx(x) => self.ld_i_vx(x), // SCHIP behavior
+            Instruction::LdVxI(x) => self.ld_vx_i(x),
+            Instruction::LdRVx(x) => self.ld_r_vx(x), // SCHIP only
+            Instruction::LdVxR(x) => self.ld_vx_r(x), // SCHIP only
+            Instruction::Unknown(word) => self.no_op(word),
         }
     }
 
@@ -202,10 +396,11 @@ impl Cpu {
         if !self.schip_mode {
             debug!("Can't scroll - not in SCHIP mode");
             return;
-        } else if !self.hires_mode {
+        } else if !self.vram.hires_mode {
             debug!("Can't scroll - not in hires mode");
             return;
         }
+        self.vram.scroll_down(n as usize);
     }
 
     ///
@@ -216,7 +411,6 @@ impl Cpu {
     pub fn cls(&mut self) {
         debug!("00E0 - CLS");
         self.vram.clear();
-        self.vram_changed = true;
     }
 
     ///
@@ -239,10 +433,11 @@ impl Cpu {
         if !self.schip_mode {
             debug!("Can't scroll - not in SCHIP mode");
             return;
-        } else if !self.hires_mode {
+        } else if !self.vram.hires_mode {
             debug!("Can't scroll - not in hires mode");
             return;
         }
+        self.vram.scroll_right(4);
     }
 
     ///
@@ -255,10 +450,11 @@ impl Cpu {
         if !self.schip_mode {
             debug!("Can't scroll - not in SCHIP mode");
             return;
-        } else if !self.hires_mode {
+        } else if !self.vram.hires_mode {
             debug!("Can't scroll - not in hires mode");
             return;
         }
+        self.vram.scroll_left(4);
     }
 
     ///
@@ -287,6 +483,7 @@ impl Cpu {
             return;
         }
         self.vram.hires_mode = false;
+        self.vram.mark_all_dirty();
     }
 
     ///
@@ -301,6 +498,7 @@ impl Cpu {
             return;
         }
         self.vram.hires_mode = true;
+        self.vram.mark_all_dirty();
     }
 
     ///
@@ -559,62 +757,27 @@ impl Cpu {
         let x = *self.v.read(vx) as usize % self.vram.get_screen_width();
         let y = *self.v.read(vy) as usize % self.vram.get_screen_height();
 
-        let sprite_data = self.ram.read_slice(self.i, sprite_len.try_into().unwrap());
-
-        // if self.i >= constants::PROGRAM_START_ADDR {
-        //     let sprite = self.vram.read_sprite((self.i, vx, vy));
-        //     match sprite {
-        //         Some(coords) => {
-        //             for sy in 0..sprite_len {
-        //                 let vy = (coords.1 + sy as usize) % self.vram.get_screen_height();
-        //                 for sx in 0..8 as u8 {
-        //                     let vx = (coords.0 + sx as usize) % self.vram.get_screen_width();
-        //                     let bit = read_bit_from_byte(&sprite_data[sy as usize], 7 - sx as u8);
-
-        //                     // if *bit == 1 {
-        //                         self.vram.write(vx, vy, self.vram.read(vx, vy) ^ bit);
-        //                     // }
-        //                 }
-        //             }
-        //         }
-        //         None => {}
-        //     }
-        // }
-
-        let mut collisions: u8 = 0;
-
-        self.v.write(0xF, 0);
-
-        for sy in 0..sprite_len {
-            let vy = (y + sy as usize) % self.vram.get_screen_height();
-            for sx in 0..8 as u8 {
-                let vx = (x + sx as usize) % self.vram.get_screen_width();
-
-                let pixel = read_bit_from_byte(&sprite_data[sy as usize], 7 - sx as u8);
-
-                if *pixel == 0 {
-                    continue;
-                }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.mark(self.i, sprite_len as u16, coverage::READ);
+        }
 
-                if self.vram.read(vx, vy) & pixel == 1 {
-                    if self.schip_mode {
-                        collisions += 1;
-                    } else {
-                        self.v.write(0xF, self.v.read(0xF) | 1)
-                    };
-                }
+        let sprite_data = self.ram.read_slice(self.i, sprite_len.try_into().unwrap());
 
-                self.vram.write(vx, vy, self.vram.read(vx, vy) ^ pixel);
-            }
+        // Drawing the same sprite with the same registers at the same place again erases it
+        let sprite_key = (self.i, vx, vy);
+        let erasing = self.vram.read_sprite(sprite_key) == Some((x, y));
+        if erasing {
+            self.vram.remove_sprite(sprite_key);
+        } else {
+            self.vram.write_sprite(sprite_key, x, y);
         }
 
-        if self.schip_mode && collisions > 0 {
-            self.v.write(0xF, collisions);
+        let collisions = self.vram.draw_sprite(x, y, sprite_data, erasing);
+        self.v.write(0xF, 0);
+        if collisions > 0 {
+            self.v
+                .write(0xF, if self.schip_mode { collisions as u8 } else { 1 });
         }
-
-        // self.vram.write_sprite((self.i, vx, vy), x, y);
-
-        self.vram_changed = true;
     }
 
     ///
@@ -655,7 +818,8 @@ impl Cpu {
     ///
     /// Fx0A - LD Vx, K
     ///
-    /// All execution stops until a key is pressed, then the value of that key is stored in Vx.
+    /// All execution stops until a key is pressed, then the value of that key is stored in Vx. Depending on
+    /// `key_wait`, execution resumes when the key goes down or, as on the COSMAC VIP, when it's released.
     ///
     pub fn ld_vx_k(&mut self, vx: u8) {
         debug!("Fx0A - LD V{}, K", vx);
@@ -732,9 +896,13 @@ impl Cpu {
         let i = self.i;
         let val = *self.v.read(vx);
 
-        self.ram.write(i, val / 100);
-        self.ram.write(i + 1, (val % 100) / 10);
-        self.ram.write(i + 2, val % 10);
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.mark(i, 3, coverage::WRITTEN);
+        }
+
+        self.write_memory(i, val / 100);
+        self.write_memory(i + 1, (val % 100) / 10);
+        self.write_memory(i + 2, val % 10);
     }
 
     ///
@@ -747,8 +915,11 @@ impl Cpu {
     ///
     pub fn ld_i_vx(&mut self, vx: u8) {
         debug!("Fx55 - LD I V{}", vx);
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.mark(self.i, vx as u16 + 1, coverage::WRITTEN);
+        }
         for i in 0..(vx + 1) as u16 {
-            self.ram.write(self.i + i, *self.v.read(i as u8));
+            self.write_memory(self.i + i, *self.v.read(i as u8));
         }
         if !self.schip_mode {
             // https://github.com/mattmikolay/chip-8/wiki/CHIP%E2%80%908-Instruction-Set
@@ -767,6 +938,9 @@ impl Cpu {
     ///
     pub fn ld_vx_i(&mut self, vx: u8) {
         debug!("Fx65 - LD V{}, I", vx);
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.mark(self.i, vx as u16 + 1, coverage::READ);
+        }
         for i in 0..(vx + 1) as u16 {
             self.v.write(i as u8, *self.ram.read(self.i + i));
         }
@@ -808,10 +982,37 @@ impl Cpu {
     ///
     /// Catches unknown or invalid opcodes
     ///
-    pub fn no_op(&self, opcode: Opcode) {
+    pub fn no_op(&self, opcode: u16) {
         debug!(
-            "Invalid opcode at address {}: {:?}",
+            "Invalid opcode at address {}: {:04X}",
             self.pc.address, opcode
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::FrameClock;
+
+    #[test]
+    fn timers_count_down_at_60_hz_whatever_the_clock() {
+        for clock in [60, 120, 600, 1000] {
+            let mut cpu = Cpu::new(false);
+            // LD V0, 120; LD DT, V0; LD ST, V0; JP 0x206
+            cpu.load_program(vec![0x60, 0x78, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x06]);
+            cpu.run(3);
+
+            let mut frame_clock = FrameClock::new(clock);
+            for second in 1..=2 {
+                for _ in 0..constants::FRAME_RATE {
+                    cpu.run(frame_clock.next_frame());
+                    cpu.tick_timers();
+                }
+                let expected = 120 - 60 * second;
+                assert_eq!(cpu.delay_timer, expected, "DT at {} Hz", clock);
+                assert_eq!(cpu.sound_timer, expected, "ST at {} Hz", clock);
+            }
+        }
+    }
+}
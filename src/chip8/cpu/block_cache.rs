@@ -0,0 +1,91 @@
+use std::rc::Rc;
+
+use crate::chip8::{Flow, Instruction, Memory};
+use crate::constants;
+
+/// Longest run of instructions decoded into a single block
+const MAX_BLOCK_LEN: usize = 64;
+
+///
+/// Block
+///
+/// A predecoded run of instructions that ends at the first instruction that doesn't fall through to the next one.
+///
+#[derive(Debug)]
+pub struct Block {
+    pub instructions: Vec<Instruction>,
+}
+
+///
+/// Block Cache
+///
+/// Predecoded blocks keyed by start address. Every byte decoded into a block is marked as code, and writing to
+/// code flushes the whole cache, since self-modifying code is rare.
+///
+#[derive(Debug)]
+pub struct BlockCache {
+    blocks: Vec<Option<Rc<Block>>>,
+    code: Vec<bool>,
+}
+
+impl BlockCache {
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            blocks: vec![None; constants::SYSTEM_RAM as usize],
+            code: vec![false; constants::SYSTEM_RAM as usize],
+        }
+    }
+
+    #[inline]
+    pub fn fetch(&mut self, address: u16, ram: &Memory) -> Rc<Block> {
+        if let Some(block) = &self.blocks[address as usize] {
+            return block.clone();
+        }
+        self.decode(address, ram)
+    }
+
+    fn decode(&mut self, address: u16, ram: &Memory) -> Rc<Block> {
+        let mut instructions = Vec::new();
+        let mut current = address;
+
+        while (current as usize + 1) < self.code.len() && instructions.len() < MAX_BLOCK_LEN {
+            let instruction = Instruction::decode(
+                (*ram.read(current) as u16) << 8 | *ram.read(current + 1) as u16,
+            );
+            instructions.push(instruction);
+            self.code[current as usize] = true;
+            self.code[current as usize + 1] = true;
+
+            let falls_through = matches!(instruction.flow(current), Flow::Next(_));
+            current += constants::OPCODE_SIZE;
+
+            // Fx0A stalls the CPU until a key is pressed, so it also ends a block
+            if !falls_through || matches!(instruction, Instruction::LdVxK(_)) {
+                break;
+            }
+        }
+
+        let block = Rc::new(Block { instructions });
+        self.blocks[address as usize] = Some(block.clone());
+        block
+    }
+
+    ///
+    /// Flushes the cache if the address belongs to a decoded block. Returns true if it did.
+    ///
+    #[inline]
+    pub fn invalidate(&mut self, address: u16) -> bool {
+        if !self.code[address as usize] {
+            return false;
+        }
+        self.clear();
+        true
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.blocks.iter_mut().for_each(|block| *block = None);
+        self.code.iter_mut().for_each(|byte| *byte = false);
+    }
+}
@@ -1,9 +1,31 @@
+use std::collections::VecDeque;
+
+///
+/// Key Event
+///
+/// A CHIP-8 key going down or up. The timestamp is in milliseconds on the clock of whatever produced the
+/// event, such as SDL's event timestamps.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: u8,
+    pub pressed: bool,
+    pub timestamp: u32,
+}
+
 ///
 /// Input Buffer
 ///
+/// Holds the state of the 16 keys, fed from a queue of key events. Events are applied in order once per
+/// frame, but a key released in the same frame it was pressed stays down until the next frame, so taps
+/// shorter than a frame still reach the game. Presses are also remembered as edges for `Fx0A`.
+///
 #[derive(Debug)]
 pub struct InputBuffer {
     buffer: [bool; 16],
+    events: VecDeque<KeyEvent>,
+    /// Keys pressed since the last frame's events were applied, one bit per key
+    pressed: u16,
 }
 
 impl InputBuffer {
@@ -11,6 +33,8 @@ impl InputBuffer {
     pub fn new() -> Self {
         Self {
             buffer: [false; 16],
+            events: VecDeque::new(),
+            pressed: 0,
         }
     }
 
@@ -19,18 +43,72 @@ impl InputBuffer {
         &self.buffer[key]
     }
 
+    ///
+    /// Sets a key right away, bypassing the event queue
+    ///
     #[inline]
-    pub fn get_buffer(&mut self) -> &mut [bool; 16] {
-        &mut self.buffer
-    }
-
-    #[inline]
-    pub fn _set(&mut self, key: usize, is_pressed: bool) {
+    pub fn set(&mut self, key: usize, is_pressed: bool) {
+        if is_pressed && !self.buffer[key] {
+            self.pressed |= 1 << key;
+        }
         self.buffer[key] = is_pressed;
     }
 
     #[inline]
     pub fn clear(&mut self) {
         self.buffer = [false; 16];
+        self.events.clear();
+        self.pressed = 0;
+    }
+
+    ///
+    /// Queues a key event, to be applied with the events of the frame it falls in
+    ///
+    pub fn push(&mut self, event: KeyEvent) {
+        self.events.push_back(event);
+    }
+
+    ///
+    /// Releases every key, e.g. when the window loses focus and key releases stop coming
+    ///
+    pub fn release_all(&mut self, timestamp: u32) {
+        for key in 0..16 {
+            self.push(KeyEvent {
+                key,
+                pressed: false,
+                timestamp,
+            });
+        }
+    }
+
+    ///
+    /// Applies the queued events up to the given time, at the start of a frame
+    ///
+    pub fn apply_events(&mut self, now: u32) {
+        self.pressed = 0;
+        while let Some(event) = self.events.front() {
+            if event.timestamp > now {
+                break;
+            }
+            // Keep the release of a key pressed in this frame, and everything after it, for the next frame
+            let key = event.key as usize;
+            if !event.pressed && self.pressed & (1 << key) != 0 {
+                break;
+            }
+            self.set(key, event.pressed);
+            self.events.pop_front();
+        }
+    }
+
+    ///
+    /// Returns the lowest key pressed since the last frame, and forgets the press
+    ///
+    pub fn take_press(&mut self) -> Option<u8> {
+        if self.pressed == 0 {
+            return None;
+        }
+        let key = self.pressed.trailing_zeros() as u8;
+        self.pressed &= !(1 << key);
+        Some(key)
     }
 }
@@ -0,0 +1,54 @@
+use crate::constants;
+
+///
+/// Frame Clock
+///
+/// Splits a clock speed into the instructions to run each 60 Hz frame. When the clock isn't a multiple of 60,
+/// the remainder is carried across frames, so every second runs exactly `hz` instructions.
+///
+#[derive(Debug, Clone)]
+pub struct FrameClock {
+    pub hz: usize,
+    /// Frames run so far
+    frame: u64,
+}
+
+impl FrameClock {
+    pub fn new(hz: usize) -> Self {
+        Self { hz, frame: 0 }
+    }
+
+    ///
+    /// Returns the number of instructions to run in the next frame
+    ///
+    pub fn next_frame(&mut self) -> usize {
+        let fps = constants::FRAME_RATE as u64;
+        let hz = self.hz as u64;
+        let ticks = (self.frame + 1) * hz / fps - self.frame * hz / fps;
+        self.frame += 1;
+        ticks as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remainder_is_spread_over_the_second() {
+        let mut clock = FrameClock::new(1_000);
+        let ticks: Vec<usize> = (0..constants::FRAME_RATE * 2)
+            .map(|_| clock.next_frame())
+            .collect();
+
+        assert!(ticks.iter().all(|ticks| *ticks == 16 || *ticks == 17));
+        assert_eq!(ticks[..constants::FRAME_RATE].iter().sum::<usize>(), 1_000);
+        assert_eq!(ticks[constants::FRAME_RATE..].iter().sum::<usize>(), 1_000);
+    }
+
+    #[test]
+    fn multiples_of_the_frame_rate_run_evenly() {
+        let mut clock = FrameClock::new(600);
+        assert!((0..constants::FRAME_RATE).all(|_| clock.next_frame() == 10));
+    }
+}
@@ -0,0 +1,265 @@
+use std::fmt;
+
+use crate::constants;
+
+///
+/// Instruction
+///
+/// A decoded CHIP-8/SCHIP instruction. Variant names follow the `Cpu` method that executes them.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Scd(u8),
+    Cls,
+    Ret,
+    Scr,
+    Scl,
+    Exit,
+    Low,
+    High,
+    Jp(u16),
+    Call(u16),
+    SeVx(u8, u8),
+    SneVx(u8, u8),
+    SeVxVy(u8, u8),
+    LdVx(u8, u8),
+    AddVx(u8, u8),
+    LdVxVy(u8, u8),
+    OrVxVy(u8, u8),
+    AndVxVy(u8, u8),
+    XorVxVy(u8, u8),
+    AddVxVy(u8, u8),
+    SubVxVy(u8, u8),
+    ShrVxVy(u8, u8),
+    SubnVxVy(u8, u8),
+    ShlVxVy(u8, u8),
+    SneVxVy(u8, u8),
+    LdI(u16),
+    JpV0(u16),
+    RndVx(u8, u8),
+    DrwVxVy(u8, u8, u8),
+    SkpVx(u8),
+    SknpVx(u8),
+    LdVxDt(u8),
+    LdVxK(u8),
+    LdDtVx(u8),
+    LdStVx(u8),
+    AddIVx(u8),
+    LdFVx(u8),
+    LdHfVx(u8),
+    LdBVx(u8),
+    LdIVx(u8),
+    LdVxI(u8),
+    LdRVx(u8),
+    LdVxR(u8),
+    Unknown(u16),
+}
+
+impl Instruction {
+    pub fn decode(instruction: u16) -> Self {
+        let opcode = (
+            ((instruction & 0xF000) >> 12) as u8,
+            ((instruction & 0x0F00) >> 8) as u8,
+            ((instruction & 0x00F0) >> 4) as u8,
+            (instruction & 0x000F) as u8,
+        );
+
+        let x = opcode.1;
+        let y = opcode.2;
+        let n = opcode.3;
+        let kk = (instruction & 0x00FF) as u8;
+        let nnn = instruction & 0x0FFF;
+
+        match opcode {
+            (0x0, 0x0, 0xC, _) => Self::Scd(n),
+            (0x0, 0x0, 0xE, 0x0) => Self::Cls,
+            (0x0, 0x0, 0xE, 0xE) => Self::Ret,
+            (0x0, 0x0, 0xF, 0xB) => Self::Scr,
+            (0x0, 0x0, 0xF, 0xC) => Self::Scl,
+            (0x0, 0x0, 0xF, 0xD) => Self::Exit,
+            (0x0, 0x0, 0xF, 0xE) => Self::Low,
+            (0x0, 0x0, 0xF, 0xF) => Self::High,
+            (0x1, _, _, _) => Self::Jp(nnn),
+            (0x2, _, _, _) => Self::Call(nnn),
+            (0x3, _, _, _) => Self::SeVx(x, kk),
+            (0x4, _, _, _) => Self::SneVx(x, kk),
+            (0x5, _, _, 0x0) => Self::SeVxVy(x, y),
+            (0x6, _, _, _) => Self::LdVx(x, kk),
+            (0x7, _, _, _) => Self::AddVx(x, kk),
+            (0x8, _, _, 0x0) => Self::LdVxVy(x, y),
+            (0x8, _, _, 0x1) => Self::OrVxVy(x, y),
+            (0x8, _, _, 0x2) => Self::AndVxVy(x, y),
+            (0x8, _, _, 0x3) => Self::XorVxVy(x, y),
+            (0x8, _, _, 0x4) => Self::AddVxVy(x, y),
+            (0x8, _, _, 0x5) => Self::SubVxVy(x, y),
+            (0x8, _, _, 0x6) => Self::ShrVxVy(x, y),
+            (0x8, _, _, 0x7) => Self::SubnVxVy(x, y),
+            (0x8, _, _, 0xE) => Self::ShlVxVy(x, y),
+            (0x9, _, _, 0x0) => Self::SneVxVy(x, y),
+            (0xA, _, _, _) => Self::LdI(nnn),
+            (0xB, _, _, _) => Self::JpV0(nnn),
+            (0xC, _, _, _) => Self::RndVx(x, kk),
+            (0xD, _, _, _) => Self::DrwVxVy(x, y, n),
+            (0xE, _, 0x9, 0xE) => Self::SkpVx(x),
+            (0xE, _, 0xA, 0x1) => Self::SknpVx(x),
+            (0xF, _, 0x0, 0x7) => Self::LdVxDt(x),
+            (0xF, _, 0x0, 0xA) => Self::LdVxK(x),
+            (0xF, _, 0x1, 0x5) => Self::LdDtVx(x),
+            (0xF, _, 0x1, 0x8) => Self::LdStVx(x),
+            (0xF, _, 0x1, 0xE) => Self::AddIVx(x),
+            (0xF, _, 0x2, 0x9) => Self::LdFVx(x),
+            (0xF, _, 0x3, 0x0) => Self::LdHfVx(x),
+            (0xF, _, 0x3, 0x3) => Self::LdBVx(x),
+            (0xF, _, 0x5, 0x5) => Self::LdIVx(x),
+            (0xF, _, 0x6, 0x5) => Self::LdVxI(x),
+            (0xF, _, 0x7, 0x5) => Self::LdRVx(x),
+            (0xF, _, 0x8, 0x5) => Self::LdVxR(x),
+            _ => Self::Unknown(instruction),
+        }
+    }
+
+    ///
+    /// Returns the opcode pattern and mnemonic of the instruction, without its operands
+    ///
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Scd(_) => "00Cn SCD n",
+            Self::Cls => "00E0 CLS",
+            Self::Ret => "00EE RET",
+            Self::Scr => "00FB SCR",
+            Self::Scl => "00FC SCL",
+            Self::Exit => "00FD EXIT",
+            Self::Low => "00FE LOW",
+            Self::High => "00FF HIGH",
+            Self::Jp(_) => "1nnn JP addr",
+            Self::Call(_) => "2nnn CALL addr",
+            Self::SeVx(..) => "3xkk SE Vx, byte",
+            Self::SneVx(..) => "4xkk SNE Vx, byte",
+            Self::SeVxVy(..) => "5xy0 SE Vx, Vy",
+            Self::LdVx(..) => "6xkk LD Vx, byte",
+            Self::AddVx(..) => "7xkk ADD Vx, byte",
+            Self::LdVxVy(..) => "8xy0 LD Vx, Vy",
+            Self::OrVxVy(..) => "8xy1 OR Vx, Vy",
+            Self::AndVxVy(..) => "8xy2 AND Vx, Vy",
+            Self::XorVxVy(..) => "8xy3 XOR Vx, Vy",
+            Self::AddVxVy(..) => "8xy4 ADD Vx, Vy",
+            Self::SubVxVy(..) => "8xy5 SUB Vx, Vy",
+            Self::ShrVxVy(..) => "8xy6 SHR Vx, Vy",
+            Self::SubnVxVy(..) => "8xy7 SUBN Vx, Vy",
+            Self::ShlVxVy(..) => "8xyE SHL Vx, Vy",
+            Self::SneVxVy(..) => "9xy0 SNE Vx, Vy",
+            Self::LdI(_) => "Annn LD I, addr",
+            Self::JpV0(_) => "Bnnn JP V0, addr",
+            Self::RndVx(..) => "Cxkk RND Vx, byte",
+            Self::DrwVxVy(..) => "Dxyn DRW Vx, Vy, n",
+            Self::SkpVx(_) => "Ex9E SKP Vx",
+            Self::SknpVx(_) => "ExA1 SKNP Vx",
+            Self::LdVxDt(_) => "Fx07 LD Vx, DT",
+            Self::LdVxK(_) => "Fx0A LD Vx, K",
+            Self::LdDtVx(_) => "Fx15 LD DT, Vx",
+            Self::LdStVx(_) => "Fx18 LD ST, Vx",
+            Self::AddIVx(_) => "Fx1E ADD I, Vx",
+            Self::LdFVx(_) => "Fx29 LD F, Vx",
+            Self::LdHfVx(_) => "Fx30 LD HF, Vx",
+            Self::LdBVx(_) => "Fx33 LD B, Vx",
+            Self::LdIVx(_) => "Fx55 LD [I], Vx",
+            Self::LdVxI(_) => "Fx65 LD Vx, [I]",
+            Self::LdRVx(_) => "Fx75 LD R, Vx",
+            Self::LdVxR(_) => "Fx85 LD Vx, R",
+            Self::Unknown(_) => "???? unknown",
+        }
+    }
+
+    ///
+    /// Returns the addresses execution can continue at after this instruction, as seen by a static analysis.
+    ///
+    /// Computed jumps (`Bnnn`) can't be followed statically, so they have no successors here.
+    ///
+    pub fn flow(&self, address: u16) -> Flow {
+        let next = address.wrapping_add(constants::OPCODE_SIZE);
+        match *self {
+            Self::Jp(target) => Flow::Jump(target),
+            Self::Call(target) => Flow::Call(target, next),
+            Self::SeVx(..)
+            | Self::SneVx(..)
+            | Self::SeVxVy(..)
+            | Self::SneVxVy(..)
+            | Self::SkpVx(..)
+            | Self::SknpVx(..) => Flow::Skip(next.wrapping_add(constants::OPCODE_SIZE), next),
+            Self::JpV0(base) => Flow::ComputedJump(base),
+            Self::Ret => Flow::Return,
+            Self::Exit => Flow::Halt,
+            _ => Flow::Next(next),
+        }
+    }
+}
+
+///
+/// Control flow out of a single instruction
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// Execution continues with the next instruction
+    Next(u16),
+    /// Unconditional jump to an address
+    Jump(u16),
+    /// Subroutine call: (callee, return address)
+    Call(u16, u16),
+    /// Conditional skip: (taken, not taken)
+    Skip(u16, u16),
+    /// Jump to an address computed at runtime, relative to the given base
+    ComputedJump(u16),
+    Return,
+    Halt,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Scd(n) => write!(f, "SCD {}", n),
+            Self::Cls => write!(f, "CLS"),
+            Self::Ret => write!(f, "RET"),
+            Self::Scr => write!(f, "SCR"),
+            Self::Scl => write!(f, "SCL"),
+            Self::Exit => write!(f, "EXIT"),
+            Self::Low => write!(f, "LOW"),
+            Self::High => write!(f, "HIGH"),
+            Self::Jp(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            Self::Call(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            Self::SeVx(x, kk) => write!(f, "SE V{:X}, 0x{:02X}", x, kk),
+            Self::SneVx(x, kk) => write!(f, "SNE V{:X}, 0x{:02X}", x, kk),
+            Self::SeVxVy(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Self::LdVx(x, kk) => write!(f, "LD V{:X}, 0x{:02X}", x, kk),
+            Self::AddVx(x, kk) => write!(f, "ADD V{:X}, 0x{:02X}", x, kk),
+            Self::LdVxVy(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Self::OrVxVy(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Self::AndVxVy(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Self::XorVxVy(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Self::AddVxVy(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Self::SubVxVy(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Self::ShrVxVy(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Self::SubnVxVy(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Self::ShlVxVy(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Self::SneVxVy(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Self::LdI(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            Self::JpV0(nnn) => write!(f, "JP V0, 0x{:03X}", nnn),
+            Self::RndVx(x, kk) => write!(f, "RND V{:X}, 0x{:02X}", x, kk),
+            Self::DrwVxVy(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Self::SkpVx(x) => write!(f, "SKP V{:X}", x),
+            Self::SknpVx(x) => write!(f, "SKNP V{:X}", x),
+            Self::LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            Self::LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            Self::LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            Self::LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            Self::AddIVx(x) => write!(f, "ADD I, V{:X}", x),
+            Self::LdFVx(x) => write!(f, "LD F, V{:X}", x),
+            Self::LdHfVx(x) => write!(f, "LD HF, V{:X}", x),
+            Self::LdBVx(x) => write!(f, "LD B, V{:X}", x),
+            Self::LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            Self::LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            Self::LdRVx(x) => write!(f, "LD R, V{:X}", x),
+            Self::LdVxR(x) => write!(f, "LD V{:X}, R", x),
+            Self::Unknown(word) => write!(f, "DW 0x{:04X}", word),
+        }
+    }
+}
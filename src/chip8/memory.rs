@@ -16,6 +16,14 @@ impl Memory {
         }
     }
 
+    ///
+    /// Returns the number of bytes of RAM
+    ///
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
     #[inline]
     pub fn read(&self, addr: u16) -> &u8 {
         &self.data[addr as usize]
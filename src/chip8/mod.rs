@@ -1,7 +1,17 @@
+pub mod coverage;
 mod cpu;
+mod frame_clock;
+mod instruction;
 mod memory;
+mod profiler;
+mod trace;
 mod video;
 
-pub use cpu::Cpu;
+pub use coverage::Coverage;
+pub use cpu::{Cpu, InputBuffer, KeyEvent, KeyWait};
+pub use frame_clock::FrameClock;
+pub use instruction::{Flow, Instruction};
 pub use memory::Memory;
-pub use video::VideoMemory;
+pub use profiler::Profiler;
+pub use trace::{read_trace, TraceWriter};
+pub use video::{AntiFlicker, VideoMemory};
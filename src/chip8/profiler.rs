@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::Instruction;
+use crate::constants;
+
+/// Longest delay timer polling loop, in instructions, that counts as spinning
+const MAX_SPIN_LOOP_LEN: u64 = 32;
+
+/// Number of addresses listed in the report
+const REPORT_ADDRESSES: usize = 50;
+
+///
+/// Profiler
+///
+/// Counts executed instructions per address, per subroutine and per opcode kind, along with the ticks spent
+/// waiting for a key (`Fx0A`) and spinning on the delay timer.
+///
+/// Subroutines are tracked with a shadow call stack. An instruction counts towards the "self" total of the
+/// innermost subroutine and towards the "total" of every subroutine on the stack.
+///
+#[derive(Debug)]
+#[must_use]
+pub struct Profiler {
+    instructions: u64,
+    addresses: HashMap<u16, (u64, Instruction)>,
+    opcodes: HashMap<&'static str, u64>,
+    subroutines: HashMap<u16, (u64, u64)>,
+    call_stack: Vec<u16>,
+    key_wait_ticks: u64,
+    delay_spin_instructions: u64,
+    delay_spin_loop: Option<u16>,
+    pending_spin_instructions: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            instructions: 0,
+            addresses: HashMap::new(),
+            opcodes: HashMap::new(),
+            subroutines: HashMap::new(),
+            call_stack: vec![constants::PROGRAM_START_ADDR],
+            key_wait_ticks: 0,
+            delay_spin_instructions: 0,
+            delay_spin_loop: None,
+            pending_spin_instructions: 0,
+        }
+    }
+
+    pub fn reset_call_stack(&mut self) {
+        self.call_stack = vec![constants::PROGRAM_START_ADDR];
+        self.delay_spin_loop = None;
+        self.pending_spin_instructions = 0;
+    }
+
+    #[inline]
+    pub fn record_key_wait(&mut self) {
+        self.key_wait_ticks += 1;
+    }
+
+    pub fn record(&mut self, address: u16, instruction: Instruction, delay_timer: u8) {
+        self.instructions += 1;
+        self.addresses.entry(address).or_insert((0, instruction)).0 += 1;
+        *self.opcodes.entry(instruction.name()).or_insert(0) += 1;
+
+        let innermost = *self.call_stack.last().unwrap();
+        self.subroutines.entry(innermost).or_default().0 += 1;
+        for (depth, entry) in self.call_stack.iter().enumerate() {
+            // Recursive subroutines only count once towards their total
+            if !self.call_stack[..depth].contains(entry) {
+                self.subroutines.entry(*entry).or_default().1 += 1;
+            }
+        }
+
+        match instruction {
+            Instruction::Call(target) => self.call_stack.push(target),
+            Instruction::Ret if self.call_stack.len() > 1 => {
+                self.call_stack.pop();
+            }
+            _ => {}
+        }
+
+        self.record_delay_spin(address, instruction, delay_timer);
+    }
+
+    ///
+    /// A loop that polls the delay timer is spinning as long as every `Fx07` in it reads a non-zero value
+    ///
+    fn record_delay_spin(&mut self, address: u16, instruction: Instruction, delay_timer: u8) {
+        if let Instruction::LdVxDt(_) = instruction {
+            if self.delay_spin_loop == Some(address) {
+                self.delay_spin_instructions += self.pending_spin_instructions + 1;
+            }
+            self.pending_spin_instructions = 0;
+            self.delay_spin_loop = if delay_timer > 0 { Some(address) } else { None };
+        } else if self.delay_spin_loop.is_some() {
+            self.pending_spin_instructions += 1;
+            if self.pending_spin_instructions > MAX_SPIN_LOOP_LEN {
+                self.delay_spin_loop = None;
+                self.pending_spin_instructions = 0;
+            }
+        }
+    }
+
+    ///
+    /// Returns the profile as text, with every table sorted by instruction count. Times are worked out from
+    /// the clock speed in instructions per second.
+    ///
+    pub fn report(&self, clock_speed: usize) -> String {
+        let total = self.instructions.max(1) as f64;
+        let percent = |count: u64| 100.0 * count as f64 / total;
+        let seconds = |ticks: u64| ticks as f64 / clock_speed as f64;
+
+        let mut report = String::new();
+        writeln!(report, "Instructions executed:     {}", self.instructions).unwrap();
+        writeln!(
+            report,
+            "Waiting for key (Fx0A):    {} ticks ({:.1}s)",
+            self.key_wait_ticks,
+            seconds(self.key_wait_ticks)
+        )
+        .unwrap();
+        writeln!(
+            report,
+            "Spinning on delay timer:   {} instructions ({:.1}%, {:.1}s)",
+            self.delay_spin_instructions,
+            percent(self.delay_spin_instructions),
+            seconds(self.delay_spin_instructions)
+        )
+        .unwrap();
+
+        let mut subroutines: Vec<_> = self.subroutines.iter().collect();
+        subroutines.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then(a.0.cmp(b.0)));
+        writeln!(report, "\nSubroutines").unwrap();
+        writeln!(report, "  entry        self       %       total       %").unwrap();
+        for (entry, (own, inclusive)) in subroutines {
+            writeln!(
+                report,
+                "  0x{:03X} {:>11} {:>6.2}% {:>11} {:>6.2}%",
+                entry,
+                own,
+                percent(*own),
+                inclusive,
+                percent(*inclusive)
+            )
+            .unwrap();
+        }
+
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then(a.0.cmp(b.0)));
+        writeln!(report, "\nAddresses (top {})", REPORT_ADDRESSES).unwrap();
+        writeln!(report, "  address     count       %  instruction").unwrap();
+        for (address, (count, instruction)) in addresses.into_iter().take(REPORT_ADDRESSES) {
+            writeln!(
+                report,
+                "  0x{:03X}  {:>11} {:>6.2}%  {}",
+                address,
+                count,
+                percent(*count),
+                instruction
+            )
+            .unwrap();
+        }
+
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        writeln!(report, "\nOpcodes").unwrap();
+        writeln!(report, "  opcode                   count       %").unwrap();
+        for (name, count) in opcodes {
+            writeln!(
+                report,
+                "  {:<18} {:>11} {:>6.2}%",
+                name,
+                count,
+                percent(*count)
+            )
+            .unwrap();
+        }
+
+        report
+    }
+}
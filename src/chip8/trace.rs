@@ -0,0 +1,45 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+///
+/// Trace Writer
+///
+/// Records the address of every executed instruction, in execution order, as one hex address per line.
+///
+#[derive(Debug)]
+pub struct TraceWriter {
+    writer: BufWriter<File>,
+}
+
+impl TraceWriter {
+    pub fn create(path: &str) -> Result<Self, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Couldn't create trace file `{}`: {}", path, e))?;
+
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn record(&mut self, address: u16) {
+        writeln!(self.writer, "{:03X}", address).expect("Failed to write to trace file");
+    }
+}
+
+///
+/// Reads a trace written by `TraceWriter`
+///
+pub fn read_trace(path: &str) -> Result<Vec<u16>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Couldn't read trace file `{}`: {}", path, e))?;
+
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            u16::from_str_radix(line.trim_start_matches("0x"), 16)
+                .map_err(|_| format!("Invalid address `{}` in trace file `{}`", line, path))
+        })
+        .collect()
+}
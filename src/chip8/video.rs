@@ -2,14 +2,55 @@ use std::collections::HashMap;
 
 use crate::constants;
 
+///
+/// Anti-Flicker
+///
+/// CHIP-8 games move sprites by erasing them with an XOR draw and drawing them again, so a sprite is often
+/// missing from the screen whenever a frame is presented between the two draws.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AntiFlicker {
+    /// Show video memory as is
+    Off,
+    /// Keep pixels turned off by a sprite erase visible until the next frame boundary
+    HoldOff,
+    /// Show the union of the current and previous frames
+    Blend,
+}
+
+impl AntiFlicker {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(Self::Off),
+            "hold" => Some(Self::HoldOff),
+            "blend" => Some(Self::Blend),
+            _ => None,
+        }
+    }
+}
+
 ///
 /// Video Memory
 ///
-#[derive(Debug)]
+/// Pixels are bit-packed into one word per row, with the leftmost pixel in the most significant bit: `u64` rows
+/// for the 64x32 lores screen and `u128` rows for the 128x64 hires screen. Sprites are drawn a whole row at a
+/// time by shifting, XORing and ANDing words.
+///
+/// Rows are widened to `u128` wherever both screens are handled the same way.
+///
+/// Rows that changed since the last frame boundary are tracked as dirty, so frontends can redraw only those.
+/// Rows whose visible pixels change at the boundary because of anti-flicker are dirty in the next frame.
+///
+#[derive(Debug, Clone)]
 #[must_use]
 pub struct VideoMemory {
     pub hires_mode: bool,
-    pub data: [[u8; constants::SCREEN_WIDTH]; constants::SCREEN_HEIGHT],
+    lores: [u64; constants::SCREEN_HEIGHT],
+    hires: [u128; constants::HIRES_SCREEN_HEIGHT],
+    pub anti_flicker: AntiFlicker,
+    held: [u128; constants::HIRES_SCREEN_HEIGHT],
+    previous: [u128; constants::HIRES_SCREEN_HEIGHT],
+    dirty_rows: u64,
     sprites: HashMap<(u16, u8, u8), (usize, usize)>,
 }
 
@@ -17,35 +58,188 @@ impl VideoMemory {
     pub fn new() -> Self {
         Self {
             hires_mode: false,
-            data: [[0; constants::SCREEN_WIDTH]; constants::SCREEN_HEIGHT],
+            lores: [0; constants::SCREEN_HEIGHT],
+            hires: [0; constants::HIRES_SCREEN_HEIGHT],
+            anti_flicker: AntiFlicker::Off,
+            held: [0; constants::HIRES_SCREEN_HEIGHT],
+            previous: [0; constants::HIRES_SCREEN_HEIGHT],
+            dirty_rows: u64::MAX,
             sprites: HashMap::new(),
         }
     }
 
-    pub fn read(&self, x: usize, y: usize) -> &u8 {
-        &self.data[y][x]
+    ///
+    /// Returns a row of the current screen, with the leftmost pixel in bit `width - 1`
+    ///
+    #[inline]
+    pub fn row(&self, y: usize) -> u128 {
+        if self.hires_mode {
+            self.hires[y]
+        } else {
+            self.lores[y] as u128
+        }
+    }
+
+    #[inline]
+    fn set_row(&mut self, y: usize, row: u128) {
+        if row != self.row(y) {
+            self.dirty_rows |= 1 << y;
+        }
+        if self.hires_mode {
+            self.hires[y] = row;
+        } else {
+            self.lores[y] = row as u64;
+        }
+    }
+
+    #[inline]
+    fn pixel_mask(&self, x: usize) -> u128 {
+        1 << (self.get_screen_width() - 1 - x)
+    }
+
+    ///
+    /// XORs a sprite onto the screen at (x, y), wrapping around the edges. Returns the number of pixels that were
+    /// turned off.
+    ///
+    /// When `erasing`, the pixels turned off are held for anti-flicker.
+    ///
+    pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], erasing: bool) -> u32 {
+        let mut collisions = 0;
+        for (sy, byte) in sprite.iter().enumerate() {
+            let vy = (y + sy) % self.get_screen_height();
+            let sprite_row = self.sprite_row(*byte, x);
+            let row = self.row(vy);
+
+            let collided = row & sprite_row;
+            collisions += collided.count_ones();
+            if erasing {
+                self.held[vy] |= collided;
+            }
+            self.set_row(vy, row ^ sprite_row);
+        }
+        collisions
+    }
+
+    ///
+    /// Shifts a byte of sprite data to column x of a row, rotating the pixels past the right edge to the left
+    ///
+    #[inline]
+    fn sprite_row(&self, byte: u8, x: usize) -> u128 {
+        if self.hires_mode {
+            ((byte as u128) << 120).rotate_right(x as u32)
+        } else {
+            ((byte as u64) << 56).rotate_right(x as u32) as u128
+        }
+    }
+
+    ///
+    /// Reads a pixel as it should be shown on screen, with anti-flicker applied
+    ///
+    pub fn read_visible(&self, x: usize, y: usize) -> u8 {
+        let row = match self.anti_flicker {
+            AntiFlicker::Off => self.row(y),
+            AntiFlicker::HoldOff => self.row(y) | self.held[y],
+            AntiFlicker::Blend => self.row(y) | self.previous[y],
+        };
+        (row & self.pixel_mask(x) != 0) as u8
+    }
+
+    ///
+    /// Marks a frame boundary: held pixels are released, the frame is kept for blending and the dirty rows are
+    /// reset
+    ///
+    pub fn end_frame(&mut self) {
+        self.dirty_rows = 0;
+        for y in 0..self.get_screen_height() {
+            let released = match self.anti_flicker {
+                AntiFlicker::Off => false,
+                AntiFlicker::HoldOff => self.held[y] & !self.row(y) != 0,
+                AntiFlicker::Blend => self.previous[y] & !self.row(y) != 0,
+            };
+            if released {
+                self.dirty_rows |= 1 << y;
+            }
+            self.previous[y] = self.row(y);
+        }
+        self.held = [0; constants::HIRES_SCREEN_HEIGHT];
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty_rows != 0
+    }
+
+    ///
+    /// Returns the rows that changed since the last frame boundary, top to bottom
+    ///
+    pub fn dirty_rows(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.get_screen_height()).filter(move |y| self.dirty_rows & (1 << y) != 0)
+    }
+
+    ///
+    /// Marks the whole screen as changed, e.g. after switching between lores and hires
+    ///
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty_rows = u64::MAX;
+    }
+
+    ///
+    /// Scrolls the screen down by n rows, filling the top with blank rows
+    ///
+    pub fn scroll_down(&mut self, n: usize) {
+        for y in (0..self.get_screen_height()).rev() {
+            let row = if y >= n { self.row(y - n) } else { 0 };
+            self.set_row(y, row);
+        }
+    }
+
+    ///
+    /// Scrolls the screen right by n pixels, filling the left with blank pixels
+    ///
+    pub fn scroll_right(&mut self, n: usize) {
+        for y in 0..self.get_screen_height() {
+            let row = self.row(y) >> n;
+            self.set_row(y, row);
+        }
     }
 
-    pub fn write(&mut self, x: usize, y: usize, value: u8) {
-        self.data[y][x] = value;
+    ///
+    /// Scrolls the screen left by n pixels, filling the right with blank pixels
+    ///
+    pub fn scroll_left(&mut self, n: usize) {
+        let width_mask = u128::MAX >> (128 - self.get_screen_width());
+        for y in 0..self.get_screen_height() {
+            let row = (self.row(y) << n) & width_mask;
+            self.set_row(y, row);
+        }
     }
 
-    pub fn _read_sprite(&self, key: (u16, u8, u8)) -> Option<(usize, usize)> {
+    pub fn read_sprite(&self, key: (u16, u8, u8)) -> Option<(usize, usize)> {
         self.sprites.get(&key).copied()
     }
 
-    pub fn _write_sprite(&mut self, key: (u16, u8, u8), x: usize, y: usize) {
+    pub fn write_sprite(&mut self, key: (u16, u8, u8), x: usize, y: usize) {
         self.sprites.insert(key, (x, y));
     }
 
+    pub fn remove_sprite(&mut self, key: (u16, u8, u8)) {
+        self.sprites.remove(&key);
+    }
+
     pub fn clear(&mut self) {
-        self.data = [[0; constants::SCREEN_WIDTH]; constants::SCREEN_HEIGHT];
+        if self.anti_flicker == AntiFlicker::HoldOff {
+            for y in 0..self.get_screen_height() {
+                self.held[y] |= self.row(y);
+            }
+        }
+        self.lores = [0; constants::SCREEN_HEIGHT];
+        self.hires = [0; constants::HIRES_SCREEN_HEIGHT];
         self.sprites = HashMap::new();
+        self.mark_all_dirty();
     }
 
     pub fn get_screen_width(&self) -> usize {
         if self.hires_mode {
-            constants::SCREEN_WIDTH
+            constants::HIRES_SCREEN_WIDTH
         } else {
             constants::SCREEN_WIDTH
         }
@@ -53,9 +247,174 @@ impl VideoMemory {
 
     pub fn get_screen_height(&self) -> usize {
         if self.hires_mode {
-            constants::SCREEN_HEIGHT
+            constants::HIRES_SCREEN_HEIGHT
         } else {
             constants::SCREEN_HEIGHT
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///
+    /// Draws a sprite pixel by pixel, the way the screen was drawn before rows were packed into bits
+    ///
+    fn draw_reference(screen: &mut [Vec<bool>], x: usize, y: usize, sprite: &[u8]) -> u32 {
+        let (width, height) = (screen[0].len(), screen.len());
+        let mut collisions = 0;
+        for (sy, byte) in sprite.iter().enumerate() {
+            for sx in 0..8 {
+                if byte & (0x80 >> sx) == 0 {
+                    continue;
+                }
+                let pixel = &mut screen[(y + sy) % height][(x + sx) % width];
+                collisions += *pixel as u32;
+                *pixel = !*pixel;
+            }
+        }
+        collisions
+    }
+
+    fn assert_matches(vram: &VideoMemory, screen: &[Vec<bool>]) {
+        for (y, row) in screen.iter().enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                assert_eq!(
+                    vram.read_visible(x, y),
+                    *pixel as u8,
+                    "pixel ({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    fn check_draws(hires_mode: bool) {
+        let mut vram = VideoMemory::new();
+        vram.hires_mode = hires_mode;
+        let (width, height) = (vram.get_screen_width(), vram.get_screen_height());
+        let mut screen = vec![vec![false; width]; height];
+
+        let sprite = [0xF0, 0x90, 0xFF, 0x81, 0x3C, 0x00, 0xA5];
+        let positions = [
+            (0, 0),
+            (3, 2),
+            (5, 4),
+            (width - 4, 0),
+            (0, height - 3),
+            (width - 1, height - 1),
+            (width - 5, height - 2),
+            (3, 2),
+        ];
+        for (x, y) in positions {
+            let expected = draw_reference(&mut screen, x, y, &sprite);
+            assert_eq!(
+                vram.draw_sprite(x, y, &sprite, false),
+                expected,
+                "sprite at ({}, {})",
+                x,
+                y
+            );
+            assert_matches(&vram, &screen);
+        }
+    }
+
+    fn with_pixels(hires_mode: bool, pixels: &[(usize, usize)]) -> VideoMemory {
+        let mut vram = VideoMemory::new();
+        vram.hires_mode = hires_mode;
+        for (x, y) in pixels {
+            vram.draw_sprite(*x, *y, &[0x80], false);
+        }
+        vram.end_frame();
+        vram
+    }
+
+    fn lit_pixels(vram: &VideoMemory) -> Vec<(usize, usize)> {
+        let mut pixels = Vec::new();
+        for y in 0..vram.get_screen_height() {
+            for x in 0..vram.get_screen_width() {
+                if vram.read_visible(x, y) == 1 {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn lores_draws_match_per_pixel_drawing() {
+        check_draws(false);
+    }
+
+    #[test]
+    fn hires_draws_match_per_pixel_drawing() {
+        check_draws(true);
+    }
+
+    #[test]
+    fn scrolling_moves_pixels_and_drops_those_past_the_edge() {
+        for hires_mode in [false, true] {
+            let mut vram = with_pixels(hires_mode, &[(0, 0), (10, 5)]);
+            let (width, height) = (vram.get_screen_width(), vram.get_screen_height());
+            vram.draw_sprite(width - 1, height - 1, &[0x80], false);
+
+            vram.scroll_down(4);
+            assert_eq!(lit_pixels(&vram), vec![(0, 4), (10, 9)]);
+            vram.scroll_right(4);
+            assert_eq!(lit_pixels(&vram), vec![(4, 4), (14, 9)]);
+            vram.scroll_left(6);
+            assert_eq!(lit_pixels(&vram), vec![(8, 9)]);
+        }
+    }
+
+    #[test]
+    fn scrolling_left_clears_the_rightmost_columns() {
+        for hires_mode in [false, true] {
+            let mut vram = VideoMemory::new();
+            vram.hires_mode = hires_mode;
+            let width = vram.get_screen_width();
+            vram.draw_sprite(width - 8, 0, &[0xFF], false);
+
+            vram.scroll_left(4);
+            let expected: Vec<_> = (width - 12..width - 4).map(|x| (x, 0)).collect();
+            assert_eq!(lit_pixels(&vram), expected);
+        }
+    }
+
+    #[test]
+    fn only_changed_rows_are_dirty() {
+        let mut vram = VideoMemory::new();
+        assert_eq!(vram.dirty_rows().count(), constants::SCREEN_HEIGHT);
+        vram.end_frame();
+        assert!(!vram.is_dirty());
+
+        vram.draw_sprite(0, 30, &[0x80, 0x00, 0x80], false);
+        assert_eq!(vram.dirty_rows().collect::<Vec<_>>(), vec![0, 30]);
+        vram.end_frame();
+
+        // Drawing nothing onto a row leaves it alone
+        vram.draw_sprite(8, 3, &[0x00, 0x40], false);
+        assert_eq!(vram.dirty_rows().collect::<Vec<_>>(), vec![4]);
+    }
+
+    #[test]
+    fn scrolling_marks_only_changed_rows() {
+        let mut vram = with_pixels(true, &[(0, 10)]);
+        vram.scroll_down(2);
+        assert_eq!(vram.dirty_rows().collect::<Vec<_>>(), vec![10, 12]);
+        vram.end_frame();
+
+        vram.scroll_right(4);
+        assert_eq!(vram.dirty_rows().collect::<Vec<_>>(), vec![12]);
+    }
+
+    #[test]
+    fn clearing_marks_the_whole_screen_dirty() {
+        let mut vram = with_pixels(false, &[(5, 5)]);
+        vram.clear();
+        assert_eq!(vram.dirty_rows().count(), constants::SCREEN_HEIGHT);
+        assert!(lit_pixels(&vram).is_empty());
+    }
+}
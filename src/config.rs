@@ -0,0 +1,172 @@
+use std::fs;
+use std::io::ErrorKind;
+
+use crate::utils;
+
+///
+/// Config
+///
+/// An INI-style settings file made of `[section]` headers followed by `key = value` entries. Lines starting
+/// with `#` or `;` are comments. Settings can be changed and saved back, keeping the rest of the file,
+/// comments included, as it was.
+///
+#[derive(Debug)]
+pub struct Config {
+    path: Option<String>,
+    lines: Vec<String>,
+}
+
+impl Config {
+    ///
+    /// Loads a settings file. A missing file loads as an empty config, which is created when it's saved.
+    ///
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("Couldn't read config file `{}`: {}", path, e)),
+        };
+
+        let config = Self {
+            path: Some(path.to_string()),
+            lines: text.lines().map(str::to_string).collect(),
+        };
+
+        for (number, line) in config.lines.iter().enumerate() {
+            if let Line::Invalid = parse_line(line) {
+                return Err(format!(
+                    "{}:{}: expected `[section]` or `key = value`, found `{}`",
+                    path,
+                    number + 1,
+                    line.trim()
+                ));
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn get<'a>(&'a self, section: &str, key: &str) -> Option<&'a str> {
+        let mut in_section = false;
+        for line in &self.lines {
+            match parse_line(line) {
+                Line::Section(name) => in_section = name == section,
+                Line::Entry(name, value) if in_section && name == key => return Some(value),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    ///
+    /// Returns the entries of a section, in file order
+    ///
+    pub fn entries<'a>(
+        &'a self,
+        section: &'a str,
+    ) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        let mut in_section = false;
+        self.lines
+            .iter()
+            .filter_map(move |line| match parse_line(line) {
+                Line::Section(name) => {
+                    in_section = name == section;
+                    None
+                }
+                Line::Entry(key, value) if in_section => Some((key, value)),
+                _ => None,
+            })
+    }
+
+    ///
+    /// Returns the names of all sections, in file order
+    ///
+    pub fn sections(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().filter_map(|line| match parse_line(line) {
+            Line::Section(name) => Some(name),
+            _ => None,
+        })
+    }
+
+    ///
+    /// Sets a value, replacing the existing entry or adding one at the end of the section
+    ///
+    pub fn set(&mut self, section: &str, key: &str, value: &str) {
+        let entry = format!("{} = {}", key, value);
+
+        let mut in_section = false;
+        let mut section_end = None;
+        for (index, line) in self.lines.iter().enumerate() {
+            match parse_line(line) {
+                Line::Section(name) => {
+                    in_section = name == section;
+                    if in_section {
+                        section_end = Some(index + 1);
+                    }
+                }
+                Line::Entry(name, _) if in_section => {
+                    if name == key {
+                        self.lines[index] = entry;
+                        return;
+                    }
+                    section_end = Some(index + 1);
+                }
+                _ => {}
+            }
+        }
+
+        match section_end {
+            Some(index) => self.lines.insert(index, entry),
+            None => {
+                if self
+                    .lines
+                    .last()
+                    .is_some_and(|line| !line.trim().is_empty())
+                {
+                    self.lines.push(String::new());
+                }
+                self.lines.push(format!("[{}]", section));
+                self.lines.push(entry);
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = self.path.as_ref().ok_or("Config has no file to save to")?;
+        let mut text = self.lines.join("\n");
+        text.push('\n');
+        fs::write(path, text).map_err(|e| format!("Couldn't write config file `{}`: {}", path, e))
+    }
+}
+
+///
+/// Returns the name of the section with the settings for one ROM, e.g. `[rom.PONG]`. It's named after the
+/// ROM, so the section is the same whichever path the ROM is loaded from.
+///
+pub fn rom_section(rom_filename: &str) -> String {
+    format!("rom.{}", utils::rom_name(rom_filename))
+}
+
+enum Line<'a> {
+    Blank,
+    Section(&'a str),
+    Entry(&'a str, &'a str),
+    Invalid,
+}
+
+fn parse_line(line: &str) -> Line<'_> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+        return Line::Blank;
+    }
+    if let Some(name) = line
+        .strip_prefix('[')
+        .and_then(|line| line.strip_suffix(']'))
+    {
+        return Line::Section(name.trim());
+    }
+    match line.split_once('=') {
+        Some((key, value)) => Line::Entry(key.trim(), value.trim()),
+        None => Line::Invalid,
+    }
+}
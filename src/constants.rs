@@ -1,25 +1,28 @@
-use sdl2::pixels::Color;
-
 // Emulator
 pub const EMULATOR_NAME: &str = "Wow! Another CHIP-8 Emulator!";
-pub const MAX_ROM_SIZE: usize = 3585;
 pub const ROM_FOLDER: &str = "./roms";
+pub const CONFIG_FILE: &str = "./chip8.ini";
+pub const SCREENSHOT_FOLDER: &str = "./screenshots";
+pub const RECORDING_FOLDER: &str = "./recordings";
 
 // Display
-pub const _HIRES_SCREEN_WIDTH: usize = 128;
-pub const _HIRES_SCREEN_HEIGHT: usize = 64;
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
 pub const VIDEO_SCALE: usize = 12;
-pub const FOREGROUND_COLOR: Color = Color::RGB(255, 176, 0); // Amber
-pub const BACKGROUND_COLOR: Color = Color::BLACK;
+pub const FRAME_RATE: usize = 60;
+pub const RECORDING_SCALE: usize = 4;
 
 // Audio
-pub const BEEP_FREQ_HZ: i32 = 440 * 8;
+pub const SAMPLE_RATE: u32 = 44_100;
+pub const BEEP_ATTACK_MS: f32 = 2.0;
+pub const BEEP_RELEASE_MS: f32 = 5.0;
+pub const AUDIO_QUEUE_FRAMES: usize = 3;
+pub const MAX_CATCH_UP_FRAMES: usize = 5;
+pub const AUDIO_RECORDING_BUFFERS: usize = 64;
 
 // CPU
-pub const SCHIP_MODE: bool = false;
-pub const TARGET_CLOCK_SPEED: usize = 120;
 pub const SYSTEM_RAM: u16 = 4096;
 pub const OPCODE_SIZE: u16 = 2;
 
@@ -1,7 +1,15 @@
 mod chip8;
+mod config;
 mod constants;
 mod fonts;
 mod platform;
+#[cfg(feature = "recompiled")]
+mod recompiled {
+    include!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/recompiled.rs"));
+}
+mod script;
+mod settings;
+mod tools;
 mod utils;
 
 use dotenv::dotenv;
@@ -9,10 +17,18 @@ use env_logger;
 use log::debug;
 
 use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
 use std::time::Duration;
 
-use chip8::Cpu;
-use platform::{Audio, Display, Keypad, Platform, Tape};
+use chip8::{Coverage, KeyEvent, Profiler, TraceWriter};
+use platform::{
+    Audio, Charset, Display, Effects, Filter, Keymap, Keypad, Pacing, Palette, Platform,
+    RecordingFormat, ScaleMode, Tape,
+};
+use script::Script;
+use settings::Settings;
 
 ///
 /// Emulator State
@@ -20,39 +36,160 @@ use platform::{Audio, Display, Keypad, Platform, Tape};
 pub enum EmulatorState {
     Quit,
     Reset,
-    Running,
     DebugMode,
     Step,
+    NextFilter,
+    NextPalette,
+    SavePalette,
+    ToggleScaleMode,
+    ToggleFullscreen,
+    ToggleVirtualKeypad,
+    /// A mouse button went down or up at a point of the window
+    Click {
+        x: i32,
+        y: i32,
+        pressed: bool,
+        timestamp: u32,
+    },
+    Redraw,
+    Screenshot {
+        scaled: bool,
+    },
+    ToggleRecording,
+    ToggleMute,
 }
 
 fn main() {
     env_logger::try_init().expect("Couldn't load env_logger");
-    dotenv().expect("Couldn't load settings from `.env` file");
+    // Env vars can also be set in a `.env` file, if there's one
+    dotenv().ok();
 
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        panic!("Must include path to ROM file!");
+    if args.len() > 1 && tools::is_command(&args[1]) {
+        if let Err(e) = tools::run(&args[1..]) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    if args[1..].iter().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", settings::help());
+        return;
     }
-    let cartridge_filename = &args[1];
 
-    // Emulator settings
+    // Emulator settings, all checked before the window opens
     let mut debug_mode = false;
+    let mut settings = exit_on_error(Settings::load(&args[1..]));
+    let mut cpu = exit_on_error(settings.cpu());
+    let mut frame_clock = exit_on_error(settings.frame_clock());
+    let pacing = exit_on_error(settings.parse("pacing", "`timer` or `audio`", Pacing::from_name));
+    let tone = exit_on_error(settings.tone());
+    let scale = exit_on_error(
+        settings.parse("scale", "a whole number of at least 1", |value| {
+            value.parse::<usize>().ok().filter(|scale| *scale > 0)
+        }),
+    );
+    let software_renderer =
+        exit_on_error(
+            settings.parse("renderer", "`gpu` or `software`", |value| match value {
+                "gpu" => Some(false),
+                "software" => Some(true),
+                _ => None,
+            }),
+        );
+    let scale_mode =
+        exit_on_error(settings.parse("scale_mode", "`integer` or `fit`", ScaleMode::from_name));
+    let filter = exit_on_error(settings.parse(
+        "filter",
+        "`nearest`, `scale2x`, `scale3x`, `epx` or `xbr`",
+        Filter::from_name,
+    ));
+    let effects = exit_on_error(settings.parse(
+        "effects",
+        "a comma-separated list of `phosphor`, `scanlines`, `grid`, `led`, `bloom` and `curvature`",
+        |names| Effects::from_names(names).ok(),
+    ));
+    let virtual_keypad =
+        exit_on_error(
+            settings.parse("virtual_keypad", "`on` or `off`", |value| match value {
+                "on" => Some(true),
+                "off" => Some(false),
+                _ => None,
+            }),
+        );
+    let screenshot_charset = exit_on_error(settings.parse(
+        "screenshot_text",
+        "`ascii` or `unicode`",
+        Charset::from_name,
+    ));
+    let recording_format = exit_on_error(settings.parse(
+        "recording_format",
+        "`gif` or `y4m`",
+        RecordingFormat::from_name,
+    ));
+    let palettes = exit_on_error(Palette::load_all(&settings.config));
+    let keymap = exit_on_error(Keymap::load(
+        &settings.config,
+        &settings.rom_section,
+        settings.get("layout").unwrap_or_default(),
+    ));
+
+    let mut tape = Tape::new();
+    tape.folder = settings.get("rom_folder").unwrap_or_default().to_string();
+    exit_on_error(load_rom(&mut tape, &settings, cpu.max_program_size()));
 
     // Set up SDL context
     let platform = Platform::new();
 
-    let mut tape = Tape::new();
-    let mut display = Display::new(&platform);
-    let audio = Audio::new(&platform.get_sdl_context());
-    let mut keypad = Keypad::new(&platform.get_sdl_context());
-    let mut cpu = Cpu::new(constants::SCHIP_MODE);
+    let mut display = Display::new(&platform, scale, software_renderer);
+    let mut audio = Audio::new(&platform.get_sdl_context(), pacing, tone);
+    display.palettes = palettes;
+    display.scale_mode = scale_mode;
+    display.filter = filter;
+    display.effects = effects;
+    display.screenshot_charset = screenshot_charset;
+    display.recording_format = recording_format;
+    display.virtual_keypad.visible = virtual_keypad;
+    display.virtual_keypad.labels = keymap.labels();
+    if let Some(name) = settings.get("palette") {
+        if !display.select_palette(name) {
+            eprintln!("Unknown palette `{}`", name);
+            process::exit(1);
+        }
+    }
+
+    let mut keypad = Keypad::new(&platform.get_sdl_context(), keymap);
+
+    cpu.load_program(tape.rom.clone());
+    #[cfg(feature = "recompiled")]
+    {
+        cpu.recompiled = Some(recompiled::run_block);
+    }
+
+    // Execution trace, used to resolve computed jumps in `cfg`
+    cpu.trace = settings
+        .get("trace")
+        .map(|path| exit_on_error(TraceWriter::create(path)));
 
-    tape.read(cartridge_filename);
+    // Coverage map, written on exit
+    let coverage_filename = settings.get("coverage").map(str::to_string);
+    if coverage_filename.is_some() {
+        cpu.coverage = Some(Coverage::new());
+    }
+
+    // Profiler report, written on exit
+    let profile_filename = settings.get("profile").map(str::to_string);
+    if profile_filename.is_some() {
+        cpu.profiler = Some(Profiler::new());
+    }
 
-    cpu.load_program(tape.rom);
+    // Input script, which ends the run when it's over
+    let mut script = settings
+        .get("script")
+        .map(|path| exit_on_error(Script::load(path)));
 
-    // Emulator timing
-    let target_timestep = 1_000 / constants::TARGET_CLOCK_SPEED as u32;
+    // Emulator timing: the CPU runs in batches of ticks, one batch per 60 Hz frame
+    let target_timestep = 1_000 / constants::FRAME_RATE as u32;
 
     let frame_timer = platform
         .get_sdl_context()
@@ -67,44 +204,191 @@ fn main() {
 
         let mut should_execute = false;
 
-        // Check inputs
-        match keypad.read_host_keypad() {
-            EmulatorState::Quit => break 'emulate,
-            EmulatorState::Reset => cpu.reset(),
-            EmulatorState::Running => {}
-            EmulatorState::DebugMode => debug_mode = !debug_mode,
-            EmulatorState::Step => should_execute = true,
+        // Check inputs. Key events are queued for the CPU, and hotkeys handled right away
+        for state in keypad.poll_events(&mut cpu.keys) {
+            match state {
+                EmulatorState::Quit => break 'emulate,
+                EmulatorState::Reset => cpu.reset(),
+                EmulatorState::DebugMode => debug_mode = !debug_mode,
+                EmulatorState::Step => should_execute = true,
+                EmulatorState::NextFilter => {
+                    display.filter = display.filter.next();
+                    debug!("Upscaling filter: {}", display.filter.name());
+                    display.draw(&cpu.vram);
+                }
+                EmulatorState::NextPalette => {
+                    display.next_palette();
+                    debug!("Palette: {}", display.palette().name);
+                    display.draw(&cpu.vram);
+                }
+                EmulatorState::SavePalette => {
+                    // Every ROM piped in would share the section
+                    if settings.rom == "-" {
+                        eprintln!("Can't save the palette of a ROM read from stdin");
+                        continue;
+                    }
+                    let name = &display.palette().name;
+                    settings.config.set(&settings.rom_section, "palette", name);
+                    if let Err(e) = settings.config.save() {
+                        eprintln!("{}", e);
+                    }
+                }
+                EmulatorState::ToggleScaleMode => display.toggle_scale_mode(),
+                EmulatorState::ToggleFullscreen => display.toggle_fullscreen(),
+                EmulatorState::ToggleVirtualKeypad => display.toggle_virtual_keypad(),
+                EmulatorState::Click {
+                    x,
+                    y,
+                    pressed,
+                    timestamp,
+                } => {
+                    if let Some(key) = display.click(x, y, pressed) {
+                        cpu.keys.push(KeyEvent {
+                            key,
+                            pressed,
+                            timestamp,
+                        });
+                    }
+                }
+                EmulatorState::Redraw => display.present(),
+                EmulatorState::Screenshot { scaled } => {
+                    match display.screenshot(&cpu.vram, &tape.name, scaled) {
+                        Ok(path) => println!("Saved screenshot to {}", path),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+                EmulatorState::ToggleMute => audio.toggle_mute(),
+                EmulatorState::ToggleRecording => {
+                    if display.is_recording() {
+                        stop_recording(&mut display, &mut audio);
+                    } else {
+                        match display.start_recording(&cpu.vram, &tape.name) {
+                            Ok(path) => {
+                                println!("Recording to {}", path);
+                                let wav_path = Path::new(&path).with_extension("wav");
+                                if let Err(e) = audio.start_recording(&wav_path.to_string_lossy()) {
+                                    eprintln!("{}", e);
+                                }
+                            }
+                            Err(e) => eprintln!("{}", e),
+                        }
+                    }
+                }
+            }
         }
-        keypad.read_keypad(cpu.keys.get_buffer());
 
-        if debug_mode {
-            if !should_execute {
-                continue;
-            }
-            debug!("Executing frame {}...", cpu.cycle);
+        if debug_mode && !should_execute {
+            continue;
         }
 
-        cpu.tick();
+        // In debug mode, each step is a frame of its own whatever the pacing
+        let frames = if debug_mode { 1 } else { audio.frames_due() };
+        for _ in 0..frames {
+            let now = frame_timer.ticks();
+            if let Some(script) = script.as_mut() {
+                if !script.begin_frame(&mut cpu, now) {
+                    break 'emulate;
+                }
+            }
+            keypad.update_macros(&mut cpu.keys, now);
+            cpu.keys.apply_events(now);
+            if debug_mode {
+                debug!("Executing frame {}...", cpu.cycle);
+                cpu.tick();
+            } else {
+                cpu.run(frame_clock.next_frame());
+            }
+            cpu.tick_timers();
+
+            if cpu.schip_mode && cpu.quit_flag {
+                break 'emulate;
+            }
 
-        if cpu.schip_mode && cpu.quit_flag {
-            break 'emulate;
-        }
+            audio.end_frame(cpu.sound_timer > 0);
 
-        if cpu.sound_timer > 0 {
-            audio.start_beep();
-        } else {
-            audio.stop_beep()
-        }
+            // The virtual keypad lights up keys as they go down and up
+            let keys = std::array::from_fn(|key| *cpu.keys.get_key(key));
+            let keys_changed = display.virtual_keypad.set_pressed(keys);
 
-        if cpu.vram_changed {
-            display.draw(&cpu.vram);
+            // Phosphor decay keeps changing what's visible after the screen stops changing
+            if cpu.vram.is_dirty() || display.is_animating() || keys_changed {
+                display.draw(&cpu.vram);
+            }
+            if let Err(e) = display.record_frame(&cpu.vram) {
+                eprintln!("{}", e);
+                stop_recording(&mut display, &mut audio);
+            }
+            cpu.vram.end_frame();
         }
 
+        // With audio pacing, the loop only waits for the queue to drain below its target
+        let timestep = match audio.pacing {
+            Pacing::Timer => target_timestep,
+            Pacing::Audio => 1,
+        };
         let dt = frame_timer.ticks() - prev_frame_tick;
-        if dt < target_timestep {
-            std::thread::sleep(Duration::from_millis((target_timestep - dt).into()));
+        if dt < timestep {
+            std::thread::sleep(Duration::from_millis((timestep - dt).into()));
         }
     }
 
+    stop_recording(&mut display, &mut audio);
+
+    if let (Some(filename), Some(coverage)) = (coverage_filename, cpu.coverage.as_ref()) {
+        fs::write(&filename, coverage.to_map()).expect("Couldn't write coverage map");
+        fs::write(
+            format!("{}.asm", filename),
+            coverage.to_disassembly(&tape.rom),
+        )
+        .expect("Couldn't write coverage disassembly");
+    }
+
+    if let (Some(filename), Some(profiler)) = (profile_filename, cpu.profiler.as_ref()) {
+        fs::write(&filename, profiler.report(frame_clock.hz))
+            .expect("Couldn't write profiler report");
+    }
+
     debug!("Exiting emulator...");
+
+    if let Some(script) = script {
+        if !script.report() {
+            process::exit(1);
+        }
+    }
+}
+
+///
+/// Reads the ROM named in the settings. A recompiled build plays the ROM it carries, unless it's given another
+/// one.
+///
+fn load_rom(tape: &mut Tape, settings: &Settings, max_size: usize) -> Result<(), String> {
+    #[cfg(feature = "recompiled")]
+    if settings.rom == recompiled::ROM_NAME {
+        tape.load(recompiled::ROM_NAME, recompiled::ROM);
+        return Ok(());
+    }
+    tape.read(&settings.rom, max_size)
+}
+
+///
+/// Stops recording the screen and the audio that goes with it
+///
+fn stop_recording(display: &mut Display, audio: &mut Audio) {
+    for result in [display.stop_recording(), audio.stop_recording()] {
+        match result {
+            Ok(Some(path)) => println!("Saved recording to {}", path),
+            Ok(None) => {}
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+///
+/// Returns the value of a result, or prints the error and exits
+///
+fn exit_on_error<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    })
 }
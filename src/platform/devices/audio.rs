@@ -1,48 +1,264 @@
+mod wav;
 mod waveforms;
 
+use std::sync::mpsc::{self, Receiver};
+
 use sdl2::{
-    audio::{AudioDevice, AudioSpecDesired},
-    Sdl,
+    audio::{AudioDevice, AudioQueue, AudioSpecDesired},
+    AudioSubsystem, Sdl,
 };
-use waveforms::SquareWave;
+pub use wav::WavWriter;
+pub use waveforms::{Beeper, Synth, Waveform};
 
 use crate::constants;
 
+///
+/// Pacing
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pacing {
+    /// The main loop sleeps until the next frame is due on the system clock, and the beep follows the sound
+    /// timer as the audio callback happens to see it
+    Timer,
+    /// Frames are emulated as the audio device consumes samples. Each frame queues its own samples, so the
+    /// beep starts and stops on frame boundaries.
+    Audio,
+}
+
+impl Pacing {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "timer" => Some(Self::Timer),
+            "audio" => Some(Self::Audio),
+            _ => None,
+        }
+    }
+}
+
+enum Output {
+    Callback(AudioDevice<Beeper>),
+    Queue {
+        queue: AudioQueue<f32>,
+        beeper: Beeper,
+    },
+}
+
+///
+/// A WAV file being written, with the buffers the audio callback played when there's one
+///
+struct Recording {
+    wav: WavWriter,
+    played: Option<Receiver<Vec<f32>>>,
+}
+
+impl Recording {
+    ///
+    /// Writes the buffers the audio callback played since the last call
+    ///
+    fn write_played(&mut self) {
+        if let Some(played) = &self.played {
+            for samples in played.try_iter() {
+                self.wav.write(&samples);
+            }
+        }
+    }
+}
+
+///
+/// Tone
+///
+/// The sound of the beep
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub waveform: Waveform,
+    pub frequency: f32,
+    /// From 0 to 1
+    pub volume: f32,
+}
+
+impl Tone {
+    ///
+    /// Creates a synth that plays the tone at the given sample rate
+    ///
+    pub fn synth(&self, sample_rate: u32) -> Synth {
+        Synth::new(
+            sample_rate,
+            self.frequency,
+            self.waveform,
+            self.volume,
+            constants::BEEP_ATTACK_MS,
+            constants::BEEP_RELEASE_MS,
+        )
+    }
+}
+
 ///
 /// Audio
 ///
+/// Plays the beep through a `Synth` at the sample rate the device was opened with, so the tone is exact
+/// whatever rate SDL settles on. F7 toggles mute.
+///
+/// With `Pacing::Audio`, the samples are queued one frame at a time instead of being pulled by a
+/// callback, and the main loop keeps about `AUDIO_QUEUE_FRAMES` frames queued, which bounds the latency.
+///
+/// The samples played can be recorded to a WAV file, which starts and stops along with screen recordings.
+/// The file is written on the main thread at the end of each frame, never from the audio callback, which
+/// only passes on copies of the buffers it played.
+///
 /// Credit: https://github.com/starrhorne/chip8-rust/blob/master/src/drivers/audio_driver.rs
 ///
 pub struct Audio {
-    device: AudioDevice<SquareWave>,
+    pub pacing: Pacing,
+    sample_rate: u32,
+    output: Output,
+    /// Frames played so far, used to spread the samples evenly when the rate isn't a multiple of 60 Hz
+    frames: u64,
+    buffer: Vec<f32>,
+    recording: Option<Recording>,
 }
 
 impl Audio {
-    pub fn new(sdl_context: &Sdl) -> Self {
+    pub fn new(sdl_context: &Sdl, pacing: Pacing, tone: Tone) -> Self {
         let audio_subsystem = sdl_context.audio().unwrap();
 
-        let audio_spec = AudioSpecDesired {
-            freq: Some(constants::BEEP_FREQ_HZ),
-            channels: Some(1),
-            samples: None,
+        let (sample_rate, output) = match pacing {
+            Pacing::Timer => open_callback(&audio_subsystem, tone),
+            Pacing::Audio => open_queue(&audio_subsystem, tone),
         };
 
-        let device = audio_subsystem
-            .open_playback(None, &audio_spec, |spec| SquareWave {
-                phase_inc: 240.0 / spec.freq as f32,
-                phase: 0.0,
-                volume: 0.25,
-            })
-            .expect("Error creating audio device");
+        Self {
+            pacing,
+            sample_rate,
+            output,
+            frames: 0,
+            buffer: Vec::new(),
+            recording: None,
+        }
+    }
+
+    ///
+    /// Returns how many frames to emulate now. With timer pacing, that's always one frame per loop.
+    ///
+    pub fn frames_due(&self) -> usize {
+        match &self.output {
+            Output::Callback(_) => 1,
+            Output::Queue { queue, .. } => {
+                let samples_per_frame = self.sample_rate as usize / constants::FRAME_RATE;
+                let queued = queue.size() as usize / std::mem::size_of::<f32>();
+                let target = samples_per_frame * constants::AUDIO_QUEUE_FRAMES;
+                // After a stall, catch up over several loops rather than all at once
+                (target.saturating_sub(queued))
+                    .div_ceil(samples_per_frame)
+                    .min(constants::MAX_CATCH_UP_FRAMES)
+            }
+        }
+    }
+
+    ///
+    /// Plays the beep for the frame that just ran, if the sound timer is running
+    ///
+    pub fn end_frame(&mut self, beeping: bool) {
+        match &mut self.output {
+            Output::Callback(device) => {
+                device.lock().synth.gate = beeping;
+                if let Some(recording) = self.recording.as_mut() {
+                    recording.write_played();
+                }
+            }
+            Output::Queue { queue, beeper } => {
+                let sample_rate = self.sample_rate as u64;
+                let fps = constants::FRAME_RATE as u64;
+                let samples =
+                    (self.frames + 1) * sample_rate / fps - self.frames * sample_rate / fps;
+                self.frames += 1;
+                self.buffer.resize(samples as usize, 0.0);
+
+                beeper.synth.gate = beeping;
+                beeper.synth.render(&mut self.buffer);
+                if let Err(e) = queue.queue_audio(&self.buffer) {
+                    eprintln!("Couldn't queue audio: {}", e);
+                }
+                if let Some(recording) = self.recording.as_mut() {
+                    recording.wav.write(&self.buffer);
+                }
+            }
+        }
+    }
 
-        Self { device }
+    ///
+    /// Starts writing the samples played to a WAV file
+    ///
+    pub fn start_recording(&mut self, path: &str) -> Result<(), String> {
+        let wav = WavWriter::create(path, self.sample_rate)?;
+        let played = match &mut self.output {
+            Output::Callback(device) => {
+                let (sender, receiver) = mpsc::sync_channel(constants::AUDIO_RECORDING_BUFFERS);
+                device.lock().recorder = Some(sender);
+                Some(receiver)
+            }
+            Output::Queue { .. } => None,
+        };
+        self.recording = Some(Recording { wav, played });
+        Ok(())
     }
 
-    pub fn start_beep(&self) {
-        self.device.resume();
+    ///
+    /// Stops recording, if there's a recording. Returns the path of the finished WAV file.
+    ///
+    pub fn stop_recording(&mut self) -> Result<Option<String>, String> {
+        let Some(mut recording) = self.recording.take() else {
+            return Ok(None);
+        };
+        // Dropping the sender ends the channel, so the last buffers played can be written out
+        self.with_beeper(|beeper| beeper.recorder = None);
+        recording.write_played();
+        recording.wav.finish().map(Some)
     }
 
-    pub fn stop_beep(&self) {
-        self.device.pause();
+    pub fn toggle_mute(&mut self) {
+        self.with_beeper(|beeper| beeper.synth.muted = !beeper.synth.muted);
     }
+
+    fn with_beeper<R>(&mut self, f: impl FnOnce(&mut Beeper) -> R) -> R {
+        match &mut self.output {
+            Output::Callback(device) => f(&mut device.lock()),
+            Output::Queue { beeper, .. } => f(beeper),
+        }
+    }
+}
+
+fn audio_spec() -> AudioSpecDesired {
+    AudioSpecDesired {
+        freq: Some(constants::SAMPLE_RATE as i32),
+        channels: Some(1),
+        samples: None,
+    }
+}
+
+fn open_callback(audio_subsystem: &AudioSubsystem, tone: Tone) -> (u32, Output) {
+    let device = audio_subsystem
+        .open_playback(None, &audio_spec(), |spec| Beeper {
+            synth: tone.synth(spec.freq as u32),
+            recorder: None,
+        })
+        .expect("Error creating audio device");
+    device.resume();
+
+    (device.spec().freq as u32, Output::Callback(device))
+}
+
+fn open_queue(audio_subsystem: &AudioSubsystem, tone: Tone) -> (u32, Output) {
+    let queue = audio_subsystem
+        .open_queue::<f32, _>(None, &audio_spec())
+        .expect("Error creating audio queue");
+    queue.resume();
+
+    let sample_rate = queue.spec().freq as u32;
+    let beeper = Beeper {
+        synth: tone.synth(sample_rate),
+        recorder: None,
+    };
+    let output = Output::Queue { queue, beeper };
+    (sample_rate, output)
 }
@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+/// Size of the RIFF and WAVE headers before the samples
+const HEADER_SIZE: u32 = 44;
+
+///
+/// WAV Writer
+///
+/// Writes mono 16-bit PCM samples to a WAV file. The sizes in the header are filled in when the file is
+/// finished. Write errors are kept until then, so samples can be written from the audio callback.
+///
+pub struct WavWriter {
+    pub path: String,
+    writer: BufWriter<File>,
+    samples: u32,
+    error: Option<io::Error>,
+}
+
+impl WavWriter {
+    pub fn create(path: &str, sample_rate: u32) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Couldn't create `{}`: {}", path, e))?;
+        let mut wav = Self {
+            path: path.to_string(),
+            writer: BufWriter::new(file),
+            samples: 0,
+            error: None,
+        };
+        wav.write_header(sample_rate)
+            .map_err(|e| format!("Couldn't write `{}`: {}", path, e))?;
+        Ok(wav)
+    }
+
+    fn write_header(&mut self, sample_rate: u32) -> io::Result<()> {
+        let w = &mut self.writer;
+        w.write_all(b"RIFF")?;
+        w.write_all(&(HEADER_SIZE - 8).to_le_bytes())?;
+        w.write_all(b"WAVEfmt ")?;
+        w.write_all(&16u32.to_le_bytes())?; // Format chunk size
+        w.write_all(&1u16.to_le_bytes())?; // PCM
+        w.write_all(&1u16.to_le_bytes())?; // Mono
+        w.write_all(&sample_rate.to_le_bytes())?;
+        w.write_all(&(sample_rate * 2).to_le_bytes())?; // Bytes per second
+        w.write_all(&2u16.to_le_bytes())?; // Bytes per sample
+        w.write_all(&16u16.to_le_bytes())?; // Bits per sample
+        w.write_all(b"data")?;
+        w.write_all(&0u32.to_le_bytes())
+    }
+
+    pub fn write(&mut self, samples: &[f32]) {
+        if self.error.is_some() {
+            return;
+        }
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            if let Err(e) = self.writer.write_all(&value.to_le_bytes()) {
+                self.error = Some(e);
+                return;
+            }
+        }
+        self.samples += samples.len() as u32;
+    }
+
+    ///
+    /// Fills in the header and closes the file. Returns its path.
+    ///
+    pub fn finish(mut self) -> Result<String, String> {
+        let data_size = self.samples * 2;
+        let result = match self.error.take() {
+            Some(e) => Err(e),
+            None => self.patch_sizes(data_size),
+        };
+        result.map_err(|e| format!("Couldn't write `{}`: {}", self.path, e))?;
+        Ok(self.path)
+    }
+
+    fn patch_sizes(&mut self, data_size: u32) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&data_size.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
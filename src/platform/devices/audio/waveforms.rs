@@ -1,21 +1,163 @@
+use std::f32::consts::PI;
+
+use std::sync::mpsc::SyncSender;
+
 use sdl2::audio::AudioCallback;
 
+/// Fraction of each cycle a pulse wave is high
+const PULSE_DUTY_CYCLE: f32 = 0.25;
+
+///
+/// Waveform
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Pulse,
+    Triangle,
+    Sine,
+    Noise,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "square" => Some(Self::Square),
+            "pulse" => Some(Self::Pulse),
+            "triangle" => Some(Self::Triangle),
+            "sine" => Some(Self::Sine),
+            "noise" => Some(Self::Noise),
+            _ => None,
+        }
+    }
+}
+
+///
+/// Synth
 ///
-/// Credit: https://github.com/starrhorne/chip8-rust/blob/master/src/drivers/audio_driver.rs
+/// Generates the beep one sample at a time. The tone fades in and out over a few milliseconds when the gate
+/// opens and closes, so starting and stopping it doesn't click.
 ///
-pub struct SquareWave {
-    pub phase_inc: f32,
-    pub phase: f32,
+#[derive(Debug, Clone)]
+pub struct Synth {
+    pub waveform: Waveform,
     pub volume: f32,
+    pub muted: bool,
+    /// Whether the beep is playing, before the envelope is applied
+    pub gate: bool,
+    phase: f32,
+    phase_inc: f32,
+    level: f32,
+    attack_step: f32,
+    release_step: f32,
+    noise: u16,
+    noise_sample: f32,
+}
+
+impl Synth {
+    pub fn new(
+        sample_rate: u32,
+        frequency: f32,
+        waveform: Waveform,
+        volume: f32,
+        attack_ms: f32,
+        release_ms: f32,
+    ) -> Self {
+        let step = |ms: f32| (1_000.0 / (ms * sample_rate as f32)).min(1.0);
+        Self {
+            waveform,
+            volume,
+            muted: false,
+            gate: false,
+            phase: 0.0,
+            phase_inc: frequency / sample_rate as f32,
+            level: 0.0,
+            attack_step: step(attack_ms),
+            release_step: step(release_ms),
+            noise: 0xACE1,
+            noise_sample: 1.0,
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        self.level = if self.gate {
+            (self.level + self.attack_step).min(1.0)
+        } else {
+            (self.level - self.release_step).max(0.0)
+        };
+        if self.level == 0.0 {
+            return 0.0;
+        }
+
+        let sample = match self.waveform {
+            Waveform::Square => square(self.phase, 0.5),
+            Waveform::Pulse => square(self.phase, PULSE_DUTY_CYCLE),
+            Waveform::Triangle => 4.0 * (self.phase - 0.5).abs() - 1.0,
+            Waveform::Sine => (2.0 * PI * self.phase).sin(),
+            Waveform::Noise => self.noise_sample,
+        };
+
+        self.phase += self.phase_inc;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+            self.next_noise();
+        }
+
+        if self.muted {
+            return 0.0;
+        }
+        sample * self.level * self.volume
+    }
+
+    pub fn render(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = self.next_sample();
+        }
+    }
+
+    ///
+    /// Steps a 16-bit Galois LFSR once per cycle, so the noise follows the tone frequency
+    ///
+    fn next_noise(&mut self) {
+        let bit = self.noise & 1;
+        self.noise >>= 1;
+        if bit == 1 {
+            self.noise ^= 0xB400;
+        }
+        self.noise_sample = if bit == 1 { 1.0 } else { -1.0 };
+    }
+}
+
+#[inline]
+fn square(phase: f32, duty_cycle: f32) -> f32 {
+    if phase < duty_cycle {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+///
+/// Beeper
+///
+/// Plays the synth, either as the SDL callback or by rendering samples to queue. The beep is started and
+/// stopped through the synth's gate.
+///
+/// While recording, the callback sends a copy of every buffer it plays to the main thread, which writes them
+/// to the WAV file. Sending never blocks: if the main thread falls too far behind, buffers are dropped.
+///
+pub struct Beeper {
+    pub synth: Synth,
+    pub recorder: Option<SyncSender<Vec<f32>>>,
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for Beeper {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        for x in out.iter_mut() {
-            *x = self.volume * if self.phase < 0.5 { 1.0 } else { -1.0 };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+        self.synth.render(out);
+        if let Some(recorder) = &self.recorder {
+            let _ = recorder.try_send(out.to_vec());
         }
     }
 }
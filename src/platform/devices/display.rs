@@ -1,30 +1,101 @@
-use sdl2::pixels::Color;
-use sdl2::render::TextureCreator;
+mod effects;
+mod filters;
+mod frame;
+mod palette;
+mod recorder;
+mod screenshot;
+mod virtual_keypad;
+
+use std::path::Path;
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{Texture, TextureCreator};
 use sdl2::ttf::Font;
-use sdl2::video::WindowContext;
+use sdl2::video::{FullscreenType, WindowContext};
 use sdl2::{rect::Rect, render::Canvas, video::Window};
 
+pub use effects::Effects;
+pub use filters::Filter;
+pub use frame::Frame;
+pub use palette::Palette;
+pub use recorder::{Recorder, RecordingFormat};
+pub use screenshot::{write_screen, Charset};
+pub use virtual_keypad::VirtualKeypad;
+
 use crate::chip8::VideoMemory;
 use crate::constants;
 use crate::platform::Platform;
 
+///
+/// Scale Mode
+///
+/// How the screen is fitted into the window. The leftover space is filled with black bars.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleMode {
+    /// Scale by the largest whole number that fits, keeping every pixel the same size
+    Integer,
+    /// Scale as large as fits, keeping the aspect ratio
+    Fit,
+}
+
+impl ScaleMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "integer" => Some(Self::Integer),
+            "fit" => Some(Self::Fit),
+            _ => None,
+        }
+    }
+}
+
 ///
 /// Display
 ///
+/// The screen is rendered into a `Frame` at its native resolution, uploaded to a streaming texture and scaled up
+/// to the window by the renderer, which can be SDL's software renderer instead of the GPU.
+///
+/// The `Filter` picks how the screen is upscaled before the renderer stretches it to the window. F2 cycles
+/// through them. With `Effects` enabled, the frame is rendered `display_scale_factor` times larger so the
+/// effects have room for detail.
+///
+/// Colors come from the current `Palette`, which F3 cycles through.
+///
+/// F9 starts and stops a `Recorder`, which captures the screen once per frame as a GIF or a Y4M stream.
+///
+/// F6 shows a `VirtualKeypad` next to the screen, which can be clicked.
+///
+/// The window can be resized and made fullscreen with F11. The screen is scaled to the window according to the
+/// `ScaleMode`, toggled with F5. Since lores and hires screens have the same aspect ratio, switching between
+/// them never changes the size of the screen in the window.
+///
 pub struct Display<'a> {
     pub canvas: Canvas<Window>,
-    pub _font: Font<'a, 'static>,
+    pub font: Font<'a, 'static>,
     _texture_creator: TextureCreator<WindowContext>,
+    texture: Texture,
+    frame: Frame,
+    pub effects: Effects,
+    pub filter: Filter,
     pub display_scale_factor: usize,
-    pub foreground_color: Color,
-    pub background_color: Color,
+    pub palettes: Vec<Palette>,
+    palette_index: usize,
+    /// Set when the whole screen has to be redrawn, such as after a palette change
+    stale: bool,
+    pub scale_mode: ScaleMode,
+    pub screenshot_charset: Charset,
+    pub recording_format: RecordingFormat,
+    recorder: Option<Recorder>,
+    pub virtual_keypad: VirtualKeypad,
 }
 
 impl<'a> Display<'a> {
-    pub fn new(platform: &'a Platform) -> Self {
-        let display_scale_factor = constants::VIDEO_SCALE;
-        let background_color = constants::BACKGROUND_COLOR;
-        let foreground_color = constants::FOREGROUND_COLOR;
+    ///
+    /// Opens a window `scale` times the size of the lores screen
+    ///
+    pub fn new(platform: &'a Platform, scale: usize, software_renderer: bool) -> Self {
+        let display_scale_factor = scale;
+        let palettes = Palette::builtin();
 
         let video_subsystem = platform
             .get_sdl_context()
@@ -38,41 +109,65 @@ impl<'a> Display<'a> {
                 (constants::SCREEN_HEIGHT * display_scale_factor) as u32,
             )
             .position_centered()
+            .resizable()
             .build()
             .expect("SDL2 failed to create window in Gpu::new");
 
-        let mut canvas = window
-            .into_canvas()
+        // Scale the screen texture with nearest-neighbor filtering
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+
+        let canvas_builder = window.into_canvas();
+        let canvas_builder = if software_renderer {
+            canvas_builder.software()
+        } else {
+            canvas_builder
+        };
+        let mut canvas = canvas_builder
             .build()
             .expect("SDL2 failed to initialize window canvas in Gpu::new");
 
-        let _font = platform
+        let font = platform
             .get_ttf_context()
             .load_font("./fonts/SperryPC_CGA.ttf", 16)
             .expect("Font does not exist");
 
         let _texture_creator = canvas.texture_creator();
+        let frame = Frame::new(constants::SCREEN_WIDTH, constants::SCREEN_HEIGHT);
+        let texture = create_texture(&_texture_creator, &frame);
 
         // Reset display
-        canvas.set_draw_color(background_color);
+        canvas.set_draw_color(palettes[0].background());
         canvas.clear();
         canvas.present();
 
         Display {
             canvas,
-            _font,
+            font,
             _texture_creator,
+            texture,
+            frame,
+            effects: Effects::new(),
+            filter: Filter::Nearest,
             display_scale_factor,
-            background_color,
-            foreground_color,
+            palettes,
+            palette_index: 0,
+            stale: true,
+            scale_mode: ScaleMode::Integer,
+            screenshot_charset: Charset::Unicode,
+            recording_format: RecordingFormat::Gif,
+            recorder: None,
+            virtual_keypad: VirtualKeypad::new(),
         }
     }
 
-    pub fn _draw_text(&mut self, text: &str, x: usize, y: usize) {
+    ///
+    /// Draws a line of text centered in an area, shrunk to fit if it's too wide
+    ///
+    pub fn draw_text(&mut self, text: &str, area: Rect, color: Color) {
         let surface = self
-            ._font
+            .font
             .render(text)
-            .solid(self.foreground_color)
+            .solid(color)
             .expect("Error drawing text");
 
         let texture = self
@@ -81,36 +176,313 @@ impl<'a> Display<'a> {
             .expect("Failed to create texture from font surface");
 
         let query = texture.query();
-        let text_width = query.width;
-        let text_height = query.height;
+        let text_width = query.width.min(area.width());
+        let text_height = query.height * text_width / query.width.max(1);
 
-        let rect = Rect::new(x as i32, y as i32, text_width, text_height);
+        let rect = Rect::from_center(area.center(), text_width, text_height);
 
         self.canvas
             .copy(&texture, None, Some(rect))
             .expect("Error rendering texture");
+
+        // SAFETY: the texture was created by this display's renderer, which is still alive
+        unsafe { texture.destroy() };
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palettes[self.palette_index]
+    }
+
+    ///
+    /// Switches to the palette with the given name. Returns false if there's none.
+    ///
+    pub fn select_palette(&mut self, name: &str) -> bool {
+        match self
+            .palettes
+            .iter()
+            .position(|palette| palette.name == name)
+        {
+            Some(index) => {
+                self.palette_index = index;
+                self.stale = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn next_palette(&mut self) {
+        self.palette_index = (self.palette_index + 1) % self.palettes.len();
+        self.stale = true;
+    }
+
+    ///
+    /// Whether the display should be redrawn even if video memory hasn't changed
+    ///
+    pub fn is_animating(&self) -> bool {
+        self.effects.is_animating()
     }
 
     pub fn draw(&mut self, vram: &VideoMemory) {
-        for y in 0..vram.get_screen_height() {
-            for x in 0..vram.get_screen_width() {
-                self.canvas.set_draw_color(if *vram.read(x, y) == 1 {
-                    self.foreground_color
-                } else {
-                    self.background_color
-                });
-
-                self.canvas
-                    .fill_rect(Rect::new(
-                        (x * self.display_scale_factor as usize) as i32,
-                        (y * self.display_scale_factor as usize) as i32,
-                        self.display_scale_factor as u32,
-                        self.display_scale_factor as u32,
-                    ))
-                    .expect(format!("Failed to draw pixel at {},{}", x, y).as_str());
+        let foreground_color = self.palette().foreground();
+        let background_color = self.palette().background();
+
+        // Without effects or filters, the frame and texture map one to one to video memory, so only the rows
+        // that changed need to be redrawn
+        let query = self.texture.query();
+        if !self.stale
+            && !self.effects.is_enabled()
+            && self.filter == Filter::Nearest
+            && self.frame.width == vram.get_screen_width()
+            && self.frame.height == vram.get_screen_height()
+            && query.width as usize == self.frame.width
+            && query.height as usize == self.frame.height
+        {
+            for y in vram.dirty_rows() {
+                self.frame
+                    .render_row(vram, y, foreground_color, background_color);
+                let row = Rect::new(0, y as i32, self.frame.width as u32, 1);
+                self.texture
+                    .update(Some(row), self.frame.row_pixels(y), self.frame.pitch())
+                    .expect("Failed to update screen texture");
             }
+            self.present();
+            return;
+        }
+        self.stale = false;
+
+        if self.effects.is_enabled() {
+            self.effects.render(
+                &mut self.frame,
+                vram,
+                self.display_scale_factor,
+                foreground_color,
+                background_color,
+            );
+        } else {
+            self.frame = self.filter.apply(vram, foreground_color, background_color);
+        }
+
+        if query.width as usize != self.frame.width || query.height as usize != self.frame.height {
+            let texture = create_texture(&self._texture_creator, &self.frame);
+            let old_texture = std::mem::replace(&mut self.texture, texture);
+            // SAFETY: the texture was created by this display's renderer, which is still alive
+            unsafe { old_texture.destroy() };
+        }
+
+        self.texture
+            .update(None, &self.frame.pixels, self.frame.pitch())
+            .expect("Failed to update screen texture");
+
+        self.present();
+    }
+
+    ///
+    /// Shows the last drawn frame again, scaled to the current size of the window
+    ///
+    pub fn present(&mut self) {
+        let (window_width, window_height) = self
+            .canvas
+            .output_size()
+            .expect("Failed to get window size");
+        let (screen_area, panel) = self.virtual_keypad.split(window_width, window_height);
+        let viewport = self.viewport(screen_area);
+
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+        self.canvas
+            .copy(&self.texture, None, Some(viewport))
+            .expect("Failed to copy screen texture to canvas");
+        if let Some(panel) = panel {
+            self.draw_virtual_keypad(panel);
         }
 
         self.canvas.present();
     }
+
+    ///
+    /// Returns the part of an area of the window the screen is drawn to, centered between black bars
+    ///
+    fn viewport(&self, area: Rect) -> Rect {
+        let (area_width, area_height) = (area.width(), area.height());
+        let frame_width = self.frame.width as u32;
+        let frame_height = self.frame.height as u32;
+
+        let integer_scale = (area_width / frame_width).min(area_height / frame_height);
+        let (width, height) = if self.scale_mode == ScaleMode::Integer && integer_scale > 0 {
+            (frame_width * integer_scale, frame_height * integer_scale)
+        } else if area_width * frame_height > area_height * frame_width {
+            (area_height * frame_width / frame_height, area_height)
+        } else {
+            (area_width, area_width * frame_height / frame_width)
+        };
+
+        Rect::new(
+            area.x() + ((area_width - width) / 2) as i32,
+            area.y() + ((area_height - height) / 2) as i32,
+            width.max(1),
+            height.max(1),
+        )
+    }
+
+    ///
+    /// Draws the virtual keypad in the palette's colors, with the keys that are down lit up
+    ///
+    fn draw_virtual_keypad(&mut self, panel: Rect) {
+        let foreground_color = self.palette().foreground();
+        let background_color = self.palette().background();
+
+        for (index, &key) in virtual_keypad::LAYOUT.iter().enumerate() {
+            let button = VirtualKeypad::button(panel, index);
+            let (fill, text) = if self.virtual_keypad.is_pressed(key) {
+                (foreground_color, background_color)
+            } else {
+                (background_color, foreground_color)
+            };
+
+            self.canvas.set_draw_color(fill);
+            self.canvas
+                .fill_rect(button)
+                .expect("Failed to draw virtual keypad");
+            self.canvas.set_draw_color(foreground_color);
+            self.canvas
+                .draw_rect(button)
+                .expect("Failed to draw virtual keypad");
+
+            // The key's digit in the top two thirds, and the host key bound to it below
+            let (width, height) = (button.width(), button.height());
+            let digit_area = Rect::new(button.x(), button.y(), width, height * 2 / 3);
+            let label_area = Rect::new(
+                button.x() + 2,
+                button.y() + (height * 2 / 3) as i32,
+                width.saturating_sub(4).max(1),
+                (height / 3).max(1),
+            );
+            self.draw_text(&format!("{:X}", key), digit_area, text);
+            let label = self.virtual_keypad.labels[key as usize].clone();
+            if !label.is_empty() {
+                self.draw_text(&label, label_area, text);
+            }
+        }
+    }
+
+    ///
+    /// Handles a mouse button going down or up at a point of the window. Returns the CHIP-8 key to press or
+    /// release, if the click was on the virtual keypad.
+    ///
+    pub fn click(&mut self, x: i32, y: i32, pressed: bool) -> Option<u8> {
+        let (window_width, window_height) = self.canvas.window().size();
+        let (output_width, output_height) = self
+            .canvas
+            .output_size()
+            .expect("Failed to get window size");
+        let (_, panel) = self.virtual_keypad.split(output_width, output_height);
+
+        // Mouse positions are in window coordinates, which differ from pixels on high-DPI displays
+        let x = x * output_width as i32 / window_width.max(1) as i32;
+        let y = y * output_height as i32 / window_height.max(1) as i32;
+        self.virtual_keypad.click(panel, x, y, pressed)
+    }
+
+    pub fn toggle_virtual_keypad(&mut self) {
+        self.virtual_keypad.visible = !self.virtual_keypad.visible;
+        self.present();
+    }
+
+    ///
+    /// Saves the screen in the current palette as a PNG, either at its native resolution or scaled up by the
+    /// display scale factor, along with a text version. Returns the path of the PNG.
+    ///
+    pub fn screenshot(
+        &self,
+        vram: &VideoMemory,
+        rom_name: &str,
+        scaled: bool,
+    ) -> Result<String, String> {
+        let mut frame = Frame::new(vram.get_screen_width(), vram.get_screen_height());
+        frame.render(
+            vram,
+            self.palette().foreground(),
+            self.palette().background(),
+        );
+        if scaled {
+            frame = frame.scaled(self.display_scale_factor);
+        }
+
+        let path = screenshot::filename(constants::SCREENSHOT_FOLDER, rom_name, "png");
+        screenshot::write_png(&frame, &path)?;
+        let text_path = Path::new(&path).with_extension("txt");
+        screenshot::write_text(vram, self.screenshot_charset, &text_path.to_string_lossy())?;
+        Ok(path)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    ///
+    /// Starts recording the screen in the current palette. Returns the path of the recording.
+    ///
+    pub fn start_recording(
+        &mut self,
+        vram: &VideoMemory,
+        rom_name: &str,
+    ) -> Result<String, String> {
+        let recorder = Recorder::start(
+            self.recording_format,
+            rom_name,
+            vram,
+            self.palette(),
+            constants::RECORDING_SCALE,
+        )?;
+        let path = recorder.path.clone();
+        self.recorder = Some(recorder);
+        Ok(path)
+    }
+
+    ///
+    /// Stops recording, if there's a recording. Returns the path of the finished recording.
+    ///
+    pub fn stop_recording(&mut self) -> Result<Option<String>, String> {
+        self.recorder.take().map(Recorder::finish).transpose()
+    }
+
+    ///
+    /// Adds a frame to the recording, if there's one. Called once per 60 Hz frame.
+    ///
+    pub fn record_frame(&mut self, vram: &VideoMemory) -> Result<(), String> {
+        match self.recorder.as_mut() {
+            Some(recorder) => recorder.record(vram),
+            None => Ok(()),
+        }
+    }
+
+    pub fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        window
+            .set_fullscreen(fullscreen)
+            .expect("Failed to toggle fullscreen");
+    }
+
+    pub fn toggle_scale_mode(&mut self) {
+        self.scale_mode = match self.scale_mode {
+            ScaleMode::Integer => ScaleMode::Fit,
+            ScaleMode::Fit => ScaleMode::Integer,
+        };
+        self.present();
+    }
+}
+
+fn create_texture(texture_creator: &TextureCreator<WindowContext>, frame: &Frame) -> Texture {
+    texture_creator
+        .create_texture_streaming(
+            PixelFormatEnum::RGBA32,
+            frame.width as u32,
+            frame.height as u32,
+        )
+        .expect("Failed to create screen texture")
 }
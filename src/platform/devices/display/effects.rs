@@ -0,0 +1,236 @@
+use sdl2::pixels::Color;
+
+use super::Frame;
+use crate::chip8::VideoMemory;
+
+/// Fraction of its brightness a turned off pixel keeps each frame
+const PHOSPHOR_DECAY: f32 = 0.6;
+
+/// Brightness below which a fading pixel is considered off
+const PHOSPHOR_CUTOFF: f32 = 1.0 / 255.0;
+
+/// Brightness of the gap between scanlines
+const SCANLINE_BRIGHTNESS: f32 = 0.55;
+
+/// Brightness of the lines between pixels
+const GRID_BRIGHTNESS: f32 = 0.35;
+
+/// Radius of a rounded pixel, relative to its size
+const LED_RADIUS: f32 = 0.45;
+
+/// Brightness of the glow around lit pixels
+const BLOOM_STRENGTH: f32 = 0.4;
+
+/// Amount of barrel distortion at the corners of the screen
+const CURVATURE: f32 = 0.06;
+
+///
+/// Effects
+///
+/// Software post-processing that makes the screen look like an old CRT or LED display. The screen is rendered
+/// at a multiple of its native resolution so each pixel has room for scanlines, grid lines and rounded corners.
+///
+/// Effects are selected with a comma-separated list of names, e.g. `phosphor,scanlines,bloom`.
+///
+#[derive(Debug, Default)]
+pub struct Effects {
+    /// Pixels that turn off fade out over several frames
+    pub phosphor: bool,
+    /// The lower part of every row is darkened
+    pub scanlines: bool,
+    /// Thin dark lines separate pixels
+    pub pixel_grid: bool,
+    /// Pixels are drawn as round dots
+    pub led_pixels: bool,
+    /// Lit pixels glow onto their neighbors
+    pub bloom: bool,
+    /// The screen bulges out like a CRT
+    pub curvature: bool,
+    brightness: Vec<f32>,
+    glow: Vec<f32>,
+}
+
+impl Effects {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_names(names: &str) -> Result<Self, String> {
+        let mut effects = Self::new();
+        for name in names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            match name {
+                "phosphor" => effects.phosphor = true,
+                "scanlines" => effects.scanlines = true,
+                "grid" => effects.pixel_grid = true,
+                "led" => effects.led_pixels = true,
+                "bloom" => effects.bloom = true,
+                "curvature" => effects.curvature = true,
+                _ => return Err(format!("Unknown display effect `{}`", name)),
+            }
+        }
+        Ok(effects)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.phosphor
+            || self.scanlines
+            || self.pixel_grid
+            || self.led_pixels
+            || self.bloom
+            || self.curvature
+    }
+
+    ///
+    /// Whether the screen keeps changing between frames without anything being drawn
+    ///
+    pub fn is_animating(&self) -> bool {
+        self.phosphor
+            && self
+                .brightness
+                .iter()
+                .any(|&level| level > 0.0 && level < 1.0)
+    }
+
+    ///
+    /// Renders video memory with the effects applied, `scale` times its native resolution
+    ///
+    pub fn render(
+        &mut self,
+        frame: &mut Frame,
+        vram: &VideoMemory,
+        scale: usize,
+        foreground_color: Color,
+        background_color: Color,
+    ) {
+        let width = vram.get_screen_width();
+        let height = vram.get_screen_height();
+        self.update_brightness(vram, width, height);
+        self.update_glow(width, height);
+
+        if frame.width != width * scale || frame.height != height * scale {
+            *frame = Frame::new(width * scale, height * scale);
+        }
+
+        for oy in 0..frame.height {
+            for ox in 0..frame.width {
+                let u = (ox as f32 + 0.5) / frame.width as f32;
+                let v = (oy as f32 + 0.5) / frame.height as f32;
+                let (u, v) = if self.curvature {
+                    match curve(u, v) {
+                        Some(uv) => uv,
+                        None => {
+                            frame.set_pixel(ox, oy, Color::BLACK);
+                            continue;
+                        }
+                    }
+                } else {
+                    (u, v)
+                };
+
+                let sx = u * width as f32;
+                let sy = v * height as f32;
+                let x = (sx as usize).min(width - 1);
+                let y = (sy as usize).min(height - 1);
+                let level =
+                    self.brightness[y * width + x] * self.mask(sx.fract(), sy.fract(), scale);
+                let glow = self.glow[y * width + x];
+
+                frame.set_pixel(
+                    ox,
+                    oy,
+                    shade(foreground_color, background_color, level, glow),
+                );
+            }
+        }
+    }
+
+    fn update_brightness(&mut self, vram: &VideoMemory, width: usize, height: usize) {
+        if self.brightness.len() != width * height {
+            self.brightness = vec![0.0; width * height];
+        }
+
+        for y in 0..height {
+            for x in 0..width {
+                let level = &mut self.brightness[y * width + x];
+                *level = if vram.read_visible(x, y) == 1 {
+                    1.0
+                } else if self.phosphor && *level * PHOSPHOR_DECAY > PHOSPHOR_CUTOFF {
+                    *level * PHOSPHOR_DECAY
+                } else {
+                    0.0
+                };
+            }
+        }
+    }
+
+    ///
+    /// Blurs the brightness of every pixel into a 3x3 neighborhood
+    ///
+    fn update_glow(&mut self, width: usize, height: usize) {
+        self.glow = vec![0.0; width * height];
+        if !self.bloom {
+            return;
+        }
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut total = 0.0;
+                for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                        total += self.brightness[ny * width + nx];
+                    }
+                }
+                self.glow[y * width + x] = BLOOM_STRENGTH * total / 9.0;
+            }
+        }
+    }
+
+    ///
+    /// Returns how bright a point inside a pixel is, given its offset from the pixel's top-left corner
+    ///
+    fn mask(&self, fx: f32, fy: f32, scale: usize) -> f32 {
+        let mut mask = 1.0;
+        if self.led_pixels && ((fx - 0.5).powi(2) + (fy - 0.5).powi(2)).sqrt() > LED_RADIUS {
+            mask = 0.0;
+        }
+        let line = 1.0 / scale as f32;
+        if self.pixel_grid && (fx < line || fy < line) {
+            mask *= GRID_BRIGHTNESS;
+        }
+        if self.scanlines && fy >= 2.0 / 3.0 {
+            mask *= SCANLINE_BRIGHTNESS;
+        }
+        mask
+    }
+}
+
+///
+/// Maps a point on the screen, in 0..1 coordinates, through a barrel distortion. Points that fall outside the
+/// bulged screen return `None`.
+///
+fn curve(u: f32, v: f32) -> Option<(f32, f32)> {
+    let cx = u * 2.0 - 1.0;
+    let cy = v * 2.0 - 1.0;
+    let cx = cx * (1.0 + CURVATURE * cy * cy);
+    let cy = cy * (1.0 + CURVATURE * cx * cx);
+    if cx.abs() > 1.0 || cy.abs() > 1.0 {
+        return None;
+    }
+    Some(((cx + 1.0) / 2.0, (cy + 1.0) / 2.0))
+}
+
+fn shade(foreground_color: Color, background_color: Color, level: f32, glow: f32) -> Color {
+    let channel = |fg: u8, bg: u8| {
+        let value = bg as f32 + (fg as f32 - bg as f32) * level + fg as f32 * glow;
+        value.round().clamp(0.0, 255.0) as u8
+    };
+    Color::RGB(
+        channel(foreground_color.r, background_color.r),
+        channel(foreground_color.g, background_color.g),
+        channel(foreground_color.b, background_color.b),
+    )
+}
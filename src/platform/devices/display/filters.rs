@@ -0,0 +1,419 @@
+use sdl2::pixels::Color;
+
+use super::Frame;
+use crate::chip8::VideoMemory;
+
+/// Scale factor of the xBR-style filter
+const XBR_SCALE: usize = 4;
+
+///
+/// Filter
+///
+/// Pixel-art upscaling filters. Every filter is a pure function from video memory to a frame a fixed number of
+/// times larger than the screen, which the renderer then scales up to the window with nearest-neighbor filtering.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Scale2x,
+    Scale3x,
+    Epx,
+    Xbr,
+}
+
+impl Filter {
+    pub const ALL: [Filter; 5] = [
+        Filter::Nearest,
+        Filter::Scale2x,
+        Filter::Scale3x,
+        Filter::Epx,
+        Filter::Xbr,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|filter| filter.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::Nearest => "nearest",
+            Filter::Scale2x => "scale2x",
+            Filter::Scale3x => "scale3x",
+            Filter::Epx => "epx",
+            Filter::Xbr => "xbr",
+        }
+    }
+
+    ///
+    /// Returns the filter after this one, wrapping around to the first
+    ///
+    pub fn next(&self) -> Self {
+        let index = Self::ALL.iter().position(|filter| filter == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    pub fn apply(
+        &self,
+        vram: &VideoMemory,
+        foreground_color: Color,
+        background_color: Color,
+    ) -> Frame {
+        match self {
+            Filter::Nearest => nearest(vram, foreground_color, background_color),
+            Filter::Scale2x => scale2x(vram, foreground_color, background_color),
+            Filter::Scale3x => scale3x(vram, foreground_color, background_color),
+            Filter::Epx => epx(vram, foreground_color, background_color),
+            Filter::Xbr => xbr(vram, foreground_color, background_color),
+        }
+    }
+}
+
+///
+/// Reads a visible pixel, repeating the edges of the screen outwards
+///
+fn pixel(vram: &VideoMemory, x: isize, y: isize) -> bool {
+    let x = x.clamp(0, vram.get_screen_width() as isize - 1) as usize;
+    let y = y.clamp(0, vram.get_screen_height() as isize - 1) as usize;
+    vram.read_visible(x, y) == 1
+}
+
+///
+/// Renders a frame `scale` times larger than the screen, with `expand` returning the `scale * scale` pixels a
+/// screen pixel turns into, row by row
+///
+fn upscale<const N: usize, F>(
+    vram: &VideoMemory,
+    scale: usize,
+    foreground_color: Color,
+    background_color: Color,
+    expand: F,
+) -> Frame
+where
+    F: Fn(isize, isize) -> [bool; N],
+{
+    debug_assert_eq!(N, scale * scale);
+    let width = vram.get_screen_width();
+    let height = vram.get_screen_height();
+    let mut frame = Frame::new(width * scale, height * scale);
+
+    for y in 0..height {
+        for x in 0..width {
+            let block = expand(x as isize, y as isize);
+            for (i, &on) in block.iter().enumerate() {
+                let color = if on {
+                    foreground_color
+                } else {
+                    background_color
+                };
+                frame.set_pixel(x * scale + i % scale, y * scale + i / scale, color);
+            }
+        }
+    }
+
+    frame
+}
+
+///
+/// The screen at its native resolution
+///
+pub fn nearest(vram: &VideoMemory, foreground_color: Color, background_color: Color) -> Frame {
+    let mut frame = Frame::new(vram.get_screen_width(), vram.get_screen_height());
+    frame.render(vram, foreground_color, background_color);
+    frame
+}
+
+///
+/// Scale2x (AdvMAME2x): every pixel becomes 2x2, and a corner takes the color of its two neighbors when they
+/// agree and the opposite neighbors don't
+///
+pub fn scale2x(vram: &VideoMemory, foreground_color: Color, background_color: Color) -> Frame {
+    upscale(vram, 2, foreground_color, background_color, |x, y| {
+        let b = pixel(vram, x, y - 1);
+        let d = pixel(vram, x - 1, y);
+        let e = pixel(vram, x, y);
+        let f = pixel(vram, x + 1, y);
+        let h = pixel(vram, x, y + 1);
+
+        if b != h && d != f {
+            [
+                if d == b { d } else { e },
+                if b == f { f } else { e },
+                if d == h { d } else { e },
+                if h == f { f } else { e },
+            ]
+        } else {
+            [e; 4]
+        }
+    })
+}
+
+///
+/// Scale3x (AdvMAME3x): every pixel becomes 3x3, extending the Scale2x rules to the edges of the block
+///
+pub fn scale3x(vram: &VideoMemory, foreground_color: Color, background_color: Color) -> Frame {
+    upscale(vram, 3, foreground_color, background_color, |x, y| {
+        let a = pixel(vram, x - 1, y - 1);
+        let b = pixel(vram, x, y - 1);
+        let c = pixel(vram, x + 1, y - 1);
+        let d = pixel(vram, x - 1, y);
+        let e = pixel(vram, x, y);
+        let f = pixel(vram, x + 1, y);
+        let g = pixel(vram, x - 1, y + 1);
+        let h = pixel(vram, x, y + 1);
+        let i = pixel(vram, x + 1, y + 1);
+
+        if b != h && d != f {
+            [
+                if d == b { d } else { e },
+                if (d == b && e != c) || (b == f && e != a) {
+                    b
+                } else {
+                    e
+                },
+                if b == f { f } else { e },
+                if (d == b && e != g) || (d == h && e != a) {
+                    d
+                } else {
+                    e
+                },
+                e,
+                if (b == f && e != i) || (h == f && e != c) {
+                    f
+                } else {
+                    e
+                },
+                if d == h { d } else { e },
+                if (d == h && e != i) || (h == f && e != g) {
+                    h
+                } else {
+                    e
+                },
+                if h == f { f } else { e },
+            ]
+        } else {
+            [e; 9]
+        }
+    })
+}
+
+///
+/// EPX, as originally formulated by Eric Johnston: a corner takes the color of its two neighbors when they
+/// agree, unless three or more of the four neighbors agree
+///
+pub fn epx(vram: &VideoMemory, foreground_color: Color, background_color: Color) -> Frame {
+    upscale(vram, 2, foreground_color, background_color, |x, y| {
+        let a = pixel(vram, x, y - 1);
+        let b = pixel(vram, x + 1, y);
+        let c = pixel(vram, x - 1, y);
+        let d = pixel(vram, x, y + 1);
+        let p = pixel(vram, x, y);
+
+        let lit = [a, b, c, d].iter().filter(|&&on| on).count();
+        if lit != 2 {
+            return [p; 4];
+        }
+
+        [
+            if c == a { a } else { p },
+            if a == b { b } else { p },
+            if d == c { c } else { p },
+            if b == d { d } else { p },
+        ]
+    })
+}
+
+///
+/// An xBR-style filter: every pixel becomes 4x4, and corners where two neighbors agree against the pixel are cut
+/// along the diagonal, blending the colors at the edge of the cut to smooth it
+///
+pub fn xbr(vram: &VideoMemory, foreground_color: Color, background_color: Color) -> Frame {
+    let width = vram.get_screen_width();
+    let height = vram.get_screen_height();
+    let mut frame = Frame::new(width * XBR_SCALE, height * XBR_SCALE);
+
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let e = pixel(vram, x, y);
+
+            // Corners as (whether it's cut, color of the cut, direction)
+            let corners = [(-1, -1), (1, -1), (-1, 1), (1, 1)].map(|(dx, dy)| {
+                let horizontal = pixel(vram, x + dx, y);
+                let vertical = pixel(vram, x, y + dy);
+                let diagonal = pixel(vram, x + dx, y + dy);
+                let opposite = pixel(vram, x - dx, y) == e || pixel(vram, x, y - dy) == e;
+                let cut = horizontal == vertical && horizontal != e && (diagonal != e || opposite);
+                (cut, horizontal, dx, dy)
+            });
+
+            for sy in 0..XBR_SCALE {
+                for sx in 0..XBR_SCALE {
+                    let fx = (sx as f32 + 0.5) / XBR_SCALE as f32;
+                    let fy = (sy as f32 + 0.5) / XBR_SCALE as f32;
+
+                    let mut level = if e { 1.0 } else { 0.0 };
+                    for &(cut, neighbor, dx, dy) in &corners {
+                        if !cut {
+                            continue;
+                        }
+                        let cx = if dx < 0 { fx } else { 1.0 - fx };
+                        let cy = if dy < 0 { fy } else { 1.0 - fy };
+                        let coverage = ((0.625 - (cx + cy)) * 4.0).clamp(0.0, 1.0);
+                        let target = if neighbor { 1.0 } else { 0.0 };
+                        level += (target - level) * coverage;
+                    }
+
+                    frame.set_pixel(
+                        x as usize * XBR_SCALE + sx,
+                        y as usize * XBR_SCALE + sy,
+                        blend(foreground_color, background_color, level),
+                    );
+                }
+            }
+        }
+    }
+
+    frame
+}
+
+fn blend(foreground_color: Color, background_color: Color, level: f32) -> Color {
+    let channel = |fg: u8, bg: u8| (bg as f32 + (fg as f32 - bg as f32) * level).round() as u8;
+    Color::RGB(
+        channel(foreground_color.r, background_color.r),
+        channel(foreground_color.g, background_color.g),
+        channel(foreground_color.b, background_color.b),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn foreground() -> Color {
+        Color::RGB(255, 255, 255)
+    }
+
+    fn background() -> Color {
+        Color::RGB(0, 0, 0)
+    }
+
+    fn screen(lit: impl Fn(usize, usize) -> bool) -> VideoMemory {
+        let mut vram = VideoMemory::new();
+        for y in 0..vram.get_screen_height() {
+            for x in 0..vram.get_screen_width() {
+                if lit(x, y) {
+                    vram.draw_sprite(x, y, &[0x80], false);
+                }
+            }
+        }
+        vram
+    }
+
+    fn is_lit(frame: &Frame, x: usize, y: usize) -> bool {
+        frame.pixels[(y * frame.width + x) * 4] > 127
+    }
+
+    fn apply(filter: Filter, vram: &VideoMemory) -> Frame {
+        filter.apply(vram, foreground(), background())
+    }
+
+    /// Scale factor of each filter, with the filters that upscale
+    const UPSCALERS: [(Filter, usize); 4] = [
+        (Filter::Scale2x, 2),
+        (Filter::Scale3x, 3),
+        (Filter::Epx, 2),
+        (Filter::Xbr, XBR_SCALE),
+    ];
+
+    #[test]
+    fn solid_fill_stays_solid() {
+        for (filter, scale) in UPSCALERS {
+            for on in [false, true] {
+                let frame = apply(filter, &screen(|_, _| on));
+                assert_eq!(frame.width, 64 * scale);
+                assert_eq!(frame.height, 32 * scale);
+                for y in 0..frame.height {
+                    for x in 0..frame.width {
+                        assert_eq!(
+                            is_lit(&frame, x, y),
+                            on,
+                            "{} at ({}, {})",
+                            filter.name(),
+                            x,
+                            y
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn isolated_pixel_keeps_its_block() {
+        let vram = screen(|x, y| (x, y) == (10, 10));
+        for filter in [Filter::Scale2x, Filter::Scale3x, Filter::Epx] {
+            let scale = UPSCALERS.iter().find(|(f, _)| *f == filter).unwrap().1;
+            let frame = apply(filter, &vram);
+            for y in 0..frame.height {
+                for x in 0..frame.width {
+                    let inside = x / scale == 10 && y / scale == 10;
+                    assert_eq!(
+                        is_lit(&frame, x, y),
+                        inside,
+                        "{} at ({}, {})",
+                        filter.name(),
+                        x,
+                        y
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn xbr_rounds_isolated_pixel() {
+        let frame = apply(Filter::Xbr, &screen(|x, y| (x, y) == (10, 10)));
+        let (x, y) = (10 * XBR_SCALE, 10 * XBR_SCALE);
+        // The middle stays lit and the corners are cut
+        assert!(is_lit(&frame, x + 1, y + 1));
+        assert!(is_lit(&frame, x + 2, y + 2));
+        for (cx, cy) in [(0, 0), (3, 0), (0, 3), (3, 3)] {
+            assert!(!is_lit(&frame, x + cx, y + cy));
+        }
+        // Nothing spreads to the neighbors
+        assert!(!is_lit(&frame, x - 1, y + 2));
+        assert!(!is_lit(&frame, x + 2, y + XBR_SCALE));
+    }
+
+    #[test]
+    fn diagonal_edge_is_smoothed() {
+        // Lit below the diagonal, so (5, 5) is on the edge and (6, 5) is just above it
+        let vram = screen(|x, y| x <= y);
+
+        for filter in [Filter::Scale2x, Filter::Epx] {
+            let frame = apply(filter, &vram);
+            // The edge pixel loses its top right corner
+            assert!(is_lit(&frame, 10, 10), "{}", filter.name());
+            assert!(!is_lit(&frame, 11, 10), "{}", filter.name());
+            assert!(is_lit(&frame, 10, 11), "{}", filter.name());
+            assert!(is_lit(&frame, 11, 11), "{}", filter.name());
+            // The pixel above the edge gains its bottom left corner
+            assert!(!is_lit(&frame, 12, 10), "{}", filter.name());
+            assert!(!is_lit(&frame, 13, 10), "{}", filter.name());
+            assert!(is_lit(&frame, 12, 11), "{}", filter.name());
+            assert!(!is_lit(&frame, 13, 11), "{}", filter.name());
+        }
+
+        let frame = apply(Filter::Scale3x, &vram);
+        for sy in 0..3 {
+            for sx in 0..3 {
+                assert_eq!(is_lit(&frame, 15 + sx, 15 + sy), (sx, sy) != (2, 0));
+            }
+        }
+
+        let frame = apply(Filter::Xbr, &vram);
+        let (x, y) = (5 * XBR_SCALE, 5 * XBR_SCALE);
+        assert!(!is_lit(&frame, x + 3, y));
+        assert!(is_lit(&frame, x, y + 3));
+        assert!(is_lit(&frame, x + XBR_SCALE, y + 3));
+    }
+}
@@ -0,0 +1,98 @@
+use sdl2::pixels::Color;
+
+use crate::chip8::VideoMemory;
+
+/// Bytes per pixel in a frame
+pub const BYTES_PER_PIXEL: usize = 4;
+
+///
+/// Frame
+///
+/// An RGBA image of the screen, ready to be uploaded to a texture or written to a file.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height * BYTES_PER_PIXEL],
+        }
+    }
+
+    ///
+    /// Renders video memory at its native resolution, with anti-flicker applied, reusing the frame's buffer
+    ///
+    pub fn render(&mut self, vram: &VideoMemory, foreground_color: Color, background_color: Color) {
+        let width = vram.get_screen_width();
+        let height = vram.get_screen_height();
+        if self.width != width || self.height != height {
+            *self = Self::new(width, height);
+        }
+
+        for y in 0..height {
+            self.render_row(vram, y, foreground_color, background_color);
+        }
+    }
+
+    ///
+    /// Renders a single row of video memory, for redrawing only the rows that changed
+    ///
+    pub fn render_row(
+        &mut self,
+        vram: &VideoMemory,
+        y: usize,
+        foreground_color: Color,
+        background_color: Color,
+    ) {
+        for x in 0..self.width {
+            let color = if vram.read_visible(x, y) == 1 {
+                foreground_color
+            } else {
+                background_color
+            };
+            self.set_pixel(x, y, color);
+        }
+    }
+
+    ///
+    /// Returns a copy of the frame scaled up by a whole number, with nearest-neighbor filtering
+    ///
+    pub fn scaled(&self, factor: usize) -> Frame {
+        let mut scaled = Frame::new(self.width * factor, self.height * factor);
+        for y in 0..scaled.height {
+            let source = (y / factor) * self.pitch();
+            let row = &self.pixels[source..source + self.pitch()];
+            for x in 0..scaled.width {
+                let offset = (y * scaled.width + x) * BYTES_PER_PIXEL;
+                let pixel = (x / factor) * BYTES_PER_PIXEL;
+                scaled.pixels[offset..offset + BYTES_PER_PIXEL]
+                    .copy_from_slice(&row[pixel..pixel + BYTES_PER_PIXEL]);
+            }
+        }
+        scaled
+    }
+
+    #[inline]
+    pub fn pitch(&self) -> usize {
+        self.width * BYTES_PER_PIXEL
+    }
+
+    #[inline]
+    pub fn row_pixels(&self, y: usize) -> &[u8] {
+        &self.pixels[y * self.pitch()..(y + 1) * self.pitch()]
+    }
+
+    #[inline]
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let offset = (y * self.width + x) * BYTES_PER_PIXEL;
+        self.pixels[offset..offset + BYTES_PER_PIXEL]
+            .copy_from_slice(&[color.r, color.g, color.b, color.a]);
+    }
+}
@@ -0,0 +1,107 @@
+use sdl2::pixels::Color;
+
+use crate::config::Config;
+
+/// Prefix of config sections that define custom palettes, e.g. `[palette.gameboy]`
+const PALETTE_SECTION_PREFIX: &str = "palette.";
+
+///
+/// Palette
+///
+/// The four colors of the screen, indexed by XO-CHIP bitplanes: background (no plane), foreground (plane 1),
+/// plane 2 and both planes. CHIP-8 and SCHIP games only use the first two.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [Color; 4],
+}
+
+impl Palette {
+    pub fn new(name: &str, colors: [u32; 4]) -> Self {
+        Self {
+            name: name.to_string(),
+            colors: colors.map(|rgb| Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)),
+        }
+    }
+
+    ///
+    /// Returns the palettes that come with the emulator, the default one first
+    ///
+    pub fn builtin() -> Vec<Palette> {
+        vec![
+            Palette::new("amber", [0x000000, 0xFFB000, 0x7F5800, 0xFFD880]),
+            Palette::new("green", [0x000000, 0x33FF33, 0x1A801A, 0x99FF99]),
+            Palette::new("lcd", [0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F]),
+            Palette::new("paper", [0xFFFFFF, 0x000000, 0xAAAAAA, 0x555555]),
+            Palette::new("octo", [0x996600, 0xFFCC00, 0xFF6600, 0x662200]),
+        ]
+    }
+
+    ///
+    /// Returns the built-in palettes followed by the custom ones from the config, which replace built-in palettes
+    /// with the same name
+    ///
+    /// A custom palette is a section named `palette.NAME` with a `colors` entry listing two to four hex colors.
+    /// Missing plane colors default to the foreground color.
+    ///
+    pub fn load_all(config: &Config) -> Result<Vec<Palette>, String> {
+        let mut palettes = Self::builtin();
+
+        for section in config.sections() {
+            let name = match section.strip_prefix(PALETTE_SECTION_PREFIX) {
+                Some(name) => name,
+                None => continue,
+            };
+            let colors = config
+                .get(section, "colors")
+                .ok_or_else(|| format!("Palette `{}` has no `colors`", name))?;
+            let palette = Self::parse(name, colors)?;
+
+            match palettes.iter_mut().find(|palette| palette.name == name) {
+                Some(existing) => *existing = palette,
+                None => palettes.push(palette),
+            }
+        }
+
+        Ok(palettes)
+    }
+
+    ///
+    /// Parses a palette from a list of hex colors such as `#000000 #FFB000`
+    ///
+    pub fn parse(name: &str, colors: &str) -> Result<Self, String> {
+        let values = colors
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|color| !color.is_empty())
+            .map(|color| {
+                u32::from_str_radix(color.trim_start_matches('#'), 16)
+                    .ok()
+                    .filter(|_| color.trim_start_matches('#').len() == 6)
+                    .ok_or_else(|| format!("Palette `{}` has an invalid color `{}`", name, color))
+            })
+            .collect::<Result<Vec<u32>, String>>()?;
+
+        if values.len() < 2 || values.len() > 4 {
+            return Err(format!(
+                "Palette `{}` must have between 2 and 4 colors, found {}",
+                name,
+                values.len()
+            ));
+        }
+
+        let foreground = values[1];
+        let color = |index: usize| values.get(index).copied().unwrap_or(foreground);
+        Ok(Self::new(name, [values[0], foreground, color(2), color(3)]))
+    }
+
+    #[inline]
+    pub fn background(&self) -> Color {
+        self.colors[0]
+    }
+
+    #[inline]
+    pub fn foreground(&self) -> Color {
+        self.colors[1]
+    }
+}
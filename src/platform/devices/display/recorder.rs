@@ -0,0 +1,214 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use super::{screenshot, Palette};
+use crate::chip8::VideoMemory;
+use crate::constants;
+
+///
+/// Recording Format
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordingFormat {
+    /// Palette-indexed animated GIF, with repeated frames merged into one
+    Gif,
+    /// Uncompressed YUV4MPEG2 stream at a constant 60 fps, for encoding with external tools
+    Y4m,
+}
+
+impl RecordingFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "gif" => Some(Self::Gif),
+            "y4m" => Some(Self::Y4m),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Gif => "gif",
+            Self::Y4m => "y4m",
+        }
+    }
+}
+
+enum Output {
+    Gif(gif::Encoder<BufWriter<File>>),
+    Y4m(BufWriter<File>),
+}
+
+///
+/// Recorder
+///
+/// Records the screen once per 60 Hz frame. The size and colors are fixed when the recording starts: the
+/// screen is resampled if the machine switches between lores and hires, and palette changes don't apply.
+///
+/// GIF delays are counted in hundredths of a second, so frame durations are rounded in a way that keeps the
+/// total in sync with the frame clock.
+///
+pub struct Recorder {
+    pub path: String,
+    output: Output,
+    width: usize,
+    height: usize,
+    scale: usize,
+    colors: [[u8; 3]; 4],
+    /// The last distinct frame as palette indices, not yet written to the GIF
+    pending: Option<Vec<u8>>,
+    /// Frame number at which the pending frame first appeared
+    pending_since: u64,
+    frames: u64,
+}
+
+impl Recorder {
+    pub fn start(
+        format: RecordingFormat,
+        rom_name: &str,
+        vram: &VideoMemory,
+        palette: &Palette,
+        scale: usize,
+    ) -> Result<Self, String> {
+        let path = screenshot::filename(constants::RECORDING_FOLDER, rom_name, format.extension());
+        screenshot::create_parent_folder(&path)?;
+        let file = File::create(&path).map_err(|e| format!("Couldn't create `{}`: {}", path, e))?;
+        let mut writer = BufWriter::new(file);
+
+        let width = vram.get_screen_width() * scale;
+        let height = vram.get_screen_height() * scale;
+        let colors = palette.colors.map(|color| [color.r, color.g, color.b]);
+
+        let output = match format {
+            RecordingFormat::Gif => {
+                let encoder =
+                    gif::Encoder::new(writer, width as u16, height as u16, &colors.concat())
+                        .and_then(|mut encoder| {
+                            encoder.set_repeat(gif::Repeat::Infinite)?;
+                            Ok(encoder)
+                        })
+                        .map_err(|e| format!("Couldn't write `{}`: {}", path, e))?;
+                Output::Gif(encoder)
+            }
+            RecordingFormat::Y4m => {
+                writeln!(
+                    writer,
+                    "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                    width,
+                    height,
+                    constants::FRAME_RATE
+                )
+                .map_err(|e| format!("Couldn't write `{}`: {}", path, e))?;
+                Output::Y4m(writer)
+            }
+        };
+
+        Ok(Self {
+            path,
+            output,
+            width,
+            height,
+            scale,
+            colors,
+            pending: None,
+            pending_since: 0,
+            frames: 0,
+        })
+    }
+
+    ///
+    /// Adds the visible screen as the next frame
+    ///
+    pub fn record(&mut self, vram: &VideoMemory) -> Result<(), String> {
+        let indices = self.sample(vram);
+
+        match &mut self.output {
+            Output::Gif(_) => {
+                if self.pending.as_ref() != Some(&indices) {
+                    self.flush_pending()?;
+                    self.pending = Some(indices);
+                    self.pending_since = self.frames;
+                }
+            }
+            Output::Y4m(writer) => {
+                let planes = y4m_planes(&indices, &self.colors);
+                writer
+                    .write_all(b"FRAME\n")
+                    .and_then(|_| writer.write_all(&planes))
+                    .map_err(|e| format!("Couldn't write `{}`: {}", self.path, e))?;
+            }
+        }
+
+        self.frames += 1;
+        Ok(())
+    }
+
+    ///
+    /// Writes the last frame and closes the file
+    ///
+    pub fn finish(mut self) -> Result<String, String> {
+        self.flush_pending()?;
+        let result = match self.output {
+            Output::Gif(encoder) => encoder.into_inner().and_then(|mut writer| writer.flush()),
+            Output::Y4m(mut writer) => writer.flush(),
+        };
+        result.map_err(|e| format!("Couldn't write `{}`: {}", self.path, e))?;
+        Ok(self.path)
+    }
+
+    ///
+    /// Reads the visible screen as palette indices at the recording's size
+    ///
+    fn sample(&self, vram: &VideoMemory) -> Vec<u8> {
+        let screen_width = vram.get_screen_width();
+        let screen_height = vram.get_screen_height();
+        let native_width = self.width / self.scale;
+        let native_height = self.height / self.scale;
+
+        let mut indices = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            let sy = (y / self.scale) * screen_height / native_height;
+            for x in 0..self.width {
+                let sx = (x / self.scale) * screen_width / native_width;
+                indices.push(vram.read_visible(sx, sy));
+            }
+        }
+        indices
+    }
+
+    fn flush_pending(&mut self) -> Result<(), String> {
+        let (encoder, indices) = match (&mut self.output, self.pending.take()) {
+            (Output::Gif(encoder), Some(indices)) => (encoder, indices),
+            _ => return Ok(()),
+        };
+
+        // Delays are rounded at both ends so they add up to the time elapsed on the frame clock
+        let centiseconds = |frame: u64| (frame * 100 + 30) / constants::FRAME_RATE as u64;
+        let delay = centiseconds(self.frames) - centiseconds(self.pending_since);
+
+        let mut frame =
+            gif::Frame::from_indexed_pixels(self.width as u16, self.height as u16, indices, None);
+        frame.delay = delay.clamp(1, u16::MAX as u64) as u16;
+        encoder
+            .write_frame(&frame)
+            .map_err(|e| format!("Couldn't write `{}`: {}", self.path, e))
+    }
+}
+
+///
+/// Converts palette indices to the Y, Cb and Cr planes of a frame, using BT.601 coefficients
+///
+fn y4m_planes(indices: &[u8], colors: &[[u8; 3]; 4]) -> Vec<u8> {
+    let yuv = colors.map(|[r, g, b]| {
+        let (r, g, b) = (r as f32, g as f32, b as f32);
+        let y = 16.0 + 0.257 * r + 0.504 * g + 0.098 * b;
+        let u = 128.0 - 0.148 * r - 0.291 * g + 0.439 * b;
+        let v = 128.0 + 0.439 * r - 0.368 * g - 0.071 * b;
+        [y, u, v].map(|value| value.round().clamp(0.0, 255.0) as u8)
+    });
+
+    let mut planes = Vec::with_capacity(indices.len() * 3);
+    planes.extend(indices.iter().map(|&index| yuv[index as usize][0]));
+    planes.extend(indices.iter().map(|&index| yuv[index as usize][1]));
+    planes.extend(indices.iter().map(|&index| yuv[index as usize][2]));
+    planes
+}
@@ -0,0 +1,141 @@
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+use super::{Frame, Palette};
+use crate::chip8::VideoMemory;
+use crate::utils;
+
+///
+/// Characters used by the text version of a screenshot
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    /// One line per row, `#` for lit pixels and `.` for unlit ones
+    Ascii,
+    /// Half-block characters, two rows per line, so the text keeps the screen's aspect ratio
+    Unicode,
+}
+
+impl Charset {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ascii" => Some(Self::Ascii),
+            "unicode" => Some(Self::Unicode),
+            _ => None,
+        }
+    }
+}
+
+///
+/// Returns a path in `folder` named after the ROM and the current time, e.g. `PONG-20240102-153000.png`
+///
+/// A name already used in the folder, whatever its extension, gets a number, e.g. `PONG-20240102-153000-2.png`,
+/// so files saved in the same second don't overwrite each other or the files that go with them.
+///
+pub fn filename(folder: &str, rom_name: &str, extension: &str) -> String {
+    let name = format!("{}-{}", rom_name, utils::timestamp());
+    let taken = |stem: &str| {
+        fs::read_dir(folder).is_ok_and(|entries| {
+            entries
+                .flatten()
+                .any(|entry| Path::new(&entry.file_name()).file_stem() == Some(OsStr::new(stem)))
+        })
+    };
+    let stem = (1..)
+        .map(|n| match n {
+            1 => name.clone(),
+            _ => format!("{}-{}", name, n),
+        })
+        .find(|stem| !taken(stem))
+        .unwrap();
+    format!("{}/{}.{}", folder, stem, extension)
+}
+
+///
+/// Saves the screen in a palette as a PNG scaled up by `scale`, or as text if the file ends in `.txt`
+///
+pub fn write_screen(
+    vram: &VideoMemory,
+    palette: &Palette,
+    scale: usize,
+    path: &str,
+) -> Result<(), String> {
+    if Path::new(path).extension().is_some_and(|ext| ext == "txt") {
+        return write_text(vram, Charset::Unicode, path);
+    }
+    let mut frame = Frame::new(vram.get_screen_width(), vram.get_screen_height());
+    frame.render(vram, palette.foreground(), palette.background());
+    write_png(&frame.scaled(scale), path)
+}
+
+///
+/// Writes a frame to a PNG file, creating its folder if needed
+///
+pub fn write_png(frame: &Frame, path: &str) -> Result<(), String> {
+    create_parent_folder(path)?;
+    let file = File::create(path).map_err(|e| format!("Couldn't create `{}`: {}", path, e))?;
+
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        frame.width as u32,
+        frame.height as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&frame.pixels))
+        .map_err(|e| format!("Couldn't write `{}`: {}", path, e))
+}
+
+///
+/// Writes the text version of the screen to a file, creating its folder if needed
+///
+pub fn write_text(vram: &VideoMemory, charset: Charset, path: &str) -> Result<(), String> {
+    create_parent_folder(path)?;
+    fs::write(path, to_text(vram, charset)).map_err(|e| format!("Couldn't write `{}`: {}", path, e))
+}
+
+///
+/// Returns the visible screen as text, for pasting into bug reports
+///
+pub fn to_text(vram: &VideoMemory, charset: Charset) -> String {
+    let width = vram.get_screen_width();
+    let height = vram.get_screen_height();
+    let lit = |x: usize, y: usize| y < height && vram.read_visible(x, y) == 1;
+
+    let mut text = String::new();
+    match charset {
+        Charset::Ascii => {
+            for y in 0..height {
+                text.extend((0..width).map(|x| if lit(x, y) { '#' } else { '.' }));
+                text.push('\n');
+            }
+        }
+        Charset::Unicode => {
+            for y in (0..height).step_by(2) {
+                text.extend((0..width).map(|x| match (lit(x, y), lit(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                }));
+                text.push('\n');
+            }
+        }
+    }
+    text
+}
+
+///
+/// Creates the folder a file goes in, if there is one
+///
+pub fn create_parent_folder(path: &str) -> Result<(), String> {
+    match Path::new(path).parent() {
+        Some(folder) if !folder.as_os_str().is_empty() => fs::create_dir_all(folder)
+            .map_err(|e| format!("Couldn't create folder `{}`: {}", folder.display(), e)),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,108 @@
+use sdl2::rect::Rect;
+
+/// CHIP-8 keys in the order they appear on the COSMAC VIP keypad, row by row
+pub const LAYOUT: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, //
+    0x4, 0x5, 0x6, 0xD, //
+    0x7, 0x8, 0x9, 0xE, //
+    0xA, 0x0, 0xB, 0xF, //
+];
+
+/// The panel takes at most this fraction of the window's width
+const MAX_WIDTH_FRACTION: u32 = 3;
+
+///
+/// Virtual Keypad
+///
+/// A 4x4 hex keypad drawn to the right of the screen. Keys light up while they're down, whether from the
+/// keyboard or the mouse, and show the first host key bound to them. Touchscreens work through the mouse
+/// events SDL makes up for touches.
+///
+#[derive(Debug, Clone)]
+pub struct VirtualKeypad {
+    pub visible: bool,
+    /// Name of the host key bound to each CHIP-8 key
+    pub labels: [String; 16],
+    pressed: [bool; 16],
+    /// Key held down with the mouse, released with the mouse button wherever the pointer is then
+    mouse_key: Option<u8>,
+}
+
+impl VirtualKeypad {
+    pub fn new() -> Self {
+        Self {
+            visible: false,
+            labels: Default::default(),
+            pressed: [false; 16],
+            mouse_key: None,
+        }
+    }
+
+    ///
+    /// Splits the window between the screen and the panel. The panel is square and sits on the right,
+    /// vertically centered.
+    ///
+    pub fn split(&self, window_width: u32, window_height: u32) -> (Rect, Option<Rect>) {
+        let window = Rect::new(0, 0, window_width.max(1), window_height.max(1));
+        if !self.visible {
+            return (window, None);
+        }
+
+        let size = window_height.min(window_width / MAX_WIDTH_FRACTION);
+        if size == 0 {
+            return (window, None);
+        }
+        let screen_width = window_width - size;
+        let screen = Rect::new(0, 0, screen_width.max(1), window_height.max(1));
+        let panel = Rect::new(
+            screen_width as i32,
+            ((window_height - size) / 2) as i32,
+            size,
+            size,
+        );
+        (screen, Some(panel))
+    }
+
+    ///
+    /// Returns the area of the button at the given position in `LAYOUT`, with a gap around it
+    ///
+    pub fn button(panel: Rect, index: usize) -> Rect {
+        let cell = panel.width() / 4;
+        let gap = (cell / 12).max(1);
+        Rect::new(
+            panel.x() + ((index % 4) as u32 * cell + gap) as i32,
+            panel.y() + ((index / 4) as u32 * cell + gap) as i32,
+            cell.saturating_sub(2 * gap).max(1),
+            cell.saturating_sub(2 * gap).max(1),
+        )
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.pressed[key as usize]
+    }
+
+    ///
+    /// Updates which keys are lit. Returns true if any changed while the panel is shown.
+    ///
+    pub fn set_pressed(&mut self, pressed: [bool; 16]) -> bool {
+        let changed = self.pressed != pressed;
+        self.pressed = pressed;
+        changed && self.visible
+    }
+
+    ///
+    /// Handles a mouse button going down or up at a point of the window. Returns the CHIP-8 key to press or
+    /// release, if any.
+    ///
+    pub fn click(&mut self, panel: Option<Rect>, x: i32, y: i32, pressed: bool) -> Option<u8> {
+        if !pressed {
+            return self.mouse_key.take();
+        }
+        let panel = panel?;
+        let key = (0..LAYOUT.len())
+            .find(|&index| Self::button(panel, index).contains_point((x, y)))
+            .map(|index| LAYOUT[index]);
+        self.mouse_key = key;
+        key
+    }
+}
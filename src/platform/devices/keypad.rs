@@ -1,89 +1,196 @@
 extern crate sdl2;
 
-use sdl2::event::Event;
-use sdl2::keyboard::{KeyboardState, Keycode, Scancode};
+mod keymap;
+mod macros;
+
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
 use sdl2::EventPump;
 use sdl2::Sdl;
 
+pub use keymap::{Hotkey, Keymap};
+pub use macros::{Macro, MacroPlayer};
+
+use crate::chip8::{InputBuffer, KeyEvent};
 use crate::EmulatorState;
 
 ///
 /// Keypad
 ///
+/// Turns SDL events into key events for the CHIP-8 keys and host actions for the hotkeys bound by a `Keymap`.
+/// Bindings are matched by the key labels of the current keyboard layout. Key events keep SDL's timestamps,
+/// so presses and releases reach the CPU in the order they happened, however short they were.
+///
 pub struct Keypad {
     event_pump: EventPump,
+    keymap: Keymap,
+    /// Host keys bound to CHIP-8 keys that are down, so a CHIP-8 key with several bindings is only released
+    /// when all of them are
+    held: Vec<Keycode>,
+    macros: MacroPlayer,
 }
 
 impl Keypad {
-    pub fn new(sdl_context: &Sdl) -> Self {
+    pub fn new(sdl_context: &Sdl, keymap: Keymap) -> Self {
         Keypad {
             event_pump: sdl_context
                 .event_pump()
                 .expect("SDL2 failed to create event pump in Keypad::new"),
+            macros: MacroPlayer::new(keymap.macros.clone()),
+            keymap,
+            held: Vec::new(),
         }
     }
 
-    pub fn read_keypad(&mut self, keys: &mut [bool; 16]) {
-        let keypad_state = KeyboardState::new(&mut self.event_pump);
-
-        keys[0x1] = keypad_state.is_scancode_pressed(Scancode::Num1);
-        keys[0x2] = keypad_state.is_scancode_pressed(Scancode::Num2);
-        keys[0x3] = keypad_state.is_scancode_pressed(Scancode::Num3);
-        keys[0xC] = keypad_state.is_scancode_pressed(Scancode::Num4);
-
-        keys[0x4] = keypad_state.is_scancode_pressed(Scancode::Q);
-        keys[0x5] = keypad_state.is_scancode_pressed(Scancode::W);
-        keys[0x6] = keypad_state.is_scancode_pressed(Scancode::E);
-        keys[0xD] = keypad_state.is_scancode_pressed(Scancode::R);
-
-        keys[0x7] = keypad_state.is_scancode_pressed(Scancode::A);
-        keys[0x8] = keypad_state.is_scancode_pressed(Scancode::S);
-        keys[0x9] = keypad_state.is_scancode_pressed(Scancode::D);
-        keys[0xE] = keypad_state.is_scancode_pressed(Scancode::F);
-
-        keys[0xA] = keypad_state.is_scancode_pressed(Scancode::Z);
-        keys[0x0] = keypad_state.is_scancode_pressed(Scancode::X);
-        keys[0xB] = keypad_state.is_scancode_pressed(Scancode::C);
-        keys[0xF] = keypad_state.is_scancode_pressed(Scancode::V);
-    }
-
-    pub fn read_host_keypad(&mut self) -> EmulatorState {
+    ///
+    /// Handles the pending events. Queues CHIP-8 key events to `keys` and returns the host actions in the
+    /// order they happened.
+    ///
+    pub fn poll_events(&mut self, keys: &mut InputBuffer) -> Vec<EmulatorState> {
+        let mut states = Vec::new();
         for event in self.event_pump.poll_iter() {
             match event {
-                // Quit
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => return EmulatorState::Quit,
+                Event::Quit { .. } => states.push(EmulatorState::Quit),
 
-                // Reset
                 Event::KeyDown {
-                    keycode: Some(Keycode::Backspace),
+                    keycode: Some(keycode),
+                    keymod,
+                    repeat,
+                    timestamp,
                     ..
+                } => {
+                    if !repeat {
+                        self.macros.host_key(keycode, true);
+                    }
+                    if !repeat && !self.held.contains(&keycode) {
+                        self.held.push(keycode);
+                        queue_key_events(&self.keymap, &self.held, keycode, true, timestamp, keys);
+                    }
+
+                    let state = match self.keymap.hotkey(keycode) {
+                        Some(hotkey) if !repeat || hotkey.repeats() => hotkey_state(hotkey, keymod),
+                        _ => continue,
+                    };
+                    states.push(state);
                 }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Delete),
+
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    timestamp,
                     ..
-                } => return EmulatorState::Reset,
+                } => {
+                    self.macros.host_key(keycode, false);
+                    if self.held.contains(&keycode) {
+                        self.held.retain(|&held| held != keycode);
+                        queue_key_events(&self.keymap, &self.held, keycode, false, timestamp, keys);
+                    }
+                }
 
-                // Toggle debug mode
-                Event::KeyDown {
-                    keycode: Some(Keycode::T),
+                // Clicks, which the display turns into presses on the virtual keypad
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    timestamp,
                     ..
-                } => return EmulatorState::DebugMode,
+                } => states.push(EmulatorState::Click {
+                    x,
+                    y,
+                    pressed: true,
+                    timestamp,
+                }),
+                Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    timestamp,
+                    ..
+                } => states.push(EmulatorState::Click {
+                    x,
+                    y,
+                    pressed: false,
+                    timestamp,
+                }),
 
-                // Step through next instruction (debug mode only)
-                Event::KeyDown {
-                    keycode: Some(Keycode::Period),
-                    repeat: false,
+                // Key releases go to the window that has focus, so let go of everything
+                Event::Window {
+                    win_event: WindowEvent::FocusLost,
+                    timestamp,
                     ..
-                } => return EmulatorState::Step,
+                } => {
+                    self.held.clear();
+                    self.macros.release_all();
+                    keys.release_all(timestamp);
+                }
+
+                // Redraw after the window was resized or uncovered
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed,
+                    ..
+                } => states.push(EmulatorState::Redraw),
 
-                // Continue
-                _ => return EmulatorState::Running,
+                _ => {}
             }
         }
-        EmulatorState::Running
+        states
+    }
+
+    ///
+    /// Advances the macros by a frame, queuing the key events they play
+    ///
+    pub fn update_macros(&mut self, keys: &mut InputBuffer, timestamp: u32) {
+        self.macros.update(keys, timestamp);
+    }
+}
+
+///
+/// Queues a press or release for each CHIP-8 key bound to a host key, unless another host key bound to the
+/// same CHIP-8 key is still down
+///
+fn queue_key_events(
+    keymap: &Keymap,
+    held: &[Keycode],
+    keycode: Keycode,
+    pressed: bool,
+    timestamp: u32,
+    keys: &mut InputBuffer,
+) {
+    for (key, bound) in keymap.keys.iter().enumerate() {
+        if !bound.contains(&keycode) {
+            continue;
+        }
+        let others_held = held
+            .iter()
+            .any(|&other| other != keycode && bound.contains(&other));
+        if !others_held {
+            keys.push(KeyEvent {
+                key: key as u8,
+                pressed,
+                timestamp,
+            });
+        }
+    }
+}
+
+fn hotkey_state(hotkey: Hotkey, keymod: Mod) -> EmulatorState {
+    match hotkey {
+        Hotkey::Quit => EmulatorState::Quit,
+        Hotkey::Reset => EmulatorState::Reset,
+        Hotkey::DebugMode => EmulatorState::DebugMode,
+        // Step through next instruction (debug mode only)
+        Hotkey::Step => EmulatorState::Step,
+        Hotkey::NextFilter => EmulatorState::NextFilter,
+        Hotkey::NextPalette => EmulatorState::NextPalette,
+        Hotkey::SavePalette => EmulatorState::SavePalette,
+        Hotkey::ToggleScaleMode => EmulatorState::ToggleScaleMode,
+        Hotkey::ToggleMute => EmulatorState::ToggleMute,
+        Hotkey::ToggleRecording => EmulatorState::ToggleRecording,
+        Hotkey::ToggleFullscreen => EmulatorState::ToggleFullscreen,
+        Hotkey::ToggleVirtualKeypad => EmulatorState::ToggleVirtualKeypad,
+        // Native resolution with Shift
+        Hotkey::Screenshot => EmulatorState::Screenshot {
+            scaled: !keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD),
+        },
     }
 }
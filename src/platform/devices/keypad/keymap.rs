@@ -0,0 +1,307 @@
+use sdl2::keyboard::Keycode;
+
+use super::Macro;
+use crate::config::Config;
+
+///
+/// Layout presets, as the labels of the host keys for CHIP-8 keys 0 to F. Each preset puts the 4x4 grid in
+/// the same place, the top left of the keyboard, on its own keyboard layout.
+///
+const QWERTY: [&str; 16] = [
+    "X", "1", "2", "3", "Q", "W", "E", "A", "S", "D", "Z", "C", "4", "R", "F", "V",
+];
+const AZERTY: [&str; 16] = [
+    "X", "1", "2", "3", "A", "Z", "E", "Q", "S", "D", "W", "C", "4", "R", "F", "V",
+];
+const QWERTZ: [&str; 16] = [
+    "X", "1", "2", "3", "Q", "W", "E", "A", "S", "D", "Y", "C", "4", "R", "F", "V",
+];
+const DVORAK: [&str; 16] = [
+    "Q", "1", "2", "3", "'", ",", ".", "A", "O", "E", ";", "J", "4", "P", "U", "K",
+];
+/// Digits on the matching keypad digits, so 2/4/6/8 fall on the arrows, and A to F on the keys around them
+const NUMPAD: [&str; 16] = [
+    "Keypad 0",
+    "Keypad 1",
+    "Keypad 2",
+    "Keypad 3",
+    "Keypad 4",
+    "Keypad 5",
+    "Keypad 6",
+    "Keypad 7",
+    "Keypad 8",
+    "Keypad 9",
+    "Keypad /",
+    "Keypad *",
+    "Keypad -",
+    "Keypad +",
+    "Keypad Enter",
+    "Keypad .",
+];
+
+///
+/// Hotkey
+///
+/// Host actions that can be bound to keys in the `[hotkeys]` section of the config file
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    Quit,
+    Reset,
+    DebugMode,
+    Step,
+    NextFilter,
+    NextPalette,
+    SavePalette,
+    ToggleScaleMode,
+    ToggleMute,
+    ToggleRecording,
+    ToggleFullscreen,
+    ToggleVirtualKeypad,
+    /// Saves a screenshot, at native resolution with Shift
+    Screenshot,
+}
+
+impl Hotkey {
+    pub const ALL: [Self; 13] = [
+        Self::Quit,
+        Self::Reset,
+        Self::DebugMode,
+        Self::Step,
+        Self::NextFilter,
+        Self::NextPalette,
+        Self::SavePalette,
+        Self::ToggleScaleMode,
+        Self::ToggleMute,
+        Self::ToggleRecording,
+        Self::ToggleFullscreen,
+        Self::ToggleVirtualKeypad,
+        Self::Screenshot,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Quit => "quit",
+            Self::Reset => "reset",
+            Self::DebugMode => "debug",
+            Self::Step => "step",
+            Self::NextFilter => "next_filter",
+            Self::NextPalette => "next_palette",
+            Self::SavePalette => "save_palette",
+            Self::ToggleScaleMode => "scale_mode",
+            Self::ToggleMute => "mute",
+            Self::ToggleRecording => "record",
+            Self::ToggleFullscreen => "fullscreen",
+            Self::ToggleVirtualKeypad => "keypad",
+            Self::Screenshot => "screenshot",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|hotkey| hotkey.name() == name)
+    }
+
+    fn default_keys(&self) -> &'static [&'static str] {
+        match self {
+            Self::Quit => &["Escape"],
+            Self::Reset => &["Backspace", "Delete"],
+            Self::DebugMode => &["T"],
+            Self::Step => &["."],
+            Self::NextFilter => &["F2"],
+            Self::NextPalette => &["F3"],
+            Self::SavePalette => &["F4"],
+            Self::ToggleScaleMode => &["F5"],
+            Self::ToggleVirtualKeypad => &["F6"],
+            Self::ToggleMute => &["F7"],
+            Self::ToggleRecording => &["F9"],
+            Self::ToggleFullscreen => &["F11"],
+            Self::Screenshot => &["F12"],
+        }
+    }
+
+    ///
+    /// Whether holding the key down repeats the action
+    ///
+    pub fn repeats(&self) -> bool {
+        matches!(self, Self::Quit | Self::Reset | Self::DebugMode)
+    }
+}
+
+///
+/// Keymap
+///
+/// The host keys bound to each CHIP-8 key and to each hotkey. Keys are named as SDL names them, which is
+/// the label on the key for letters and digits, e.g. `W`, `Up`, `Keypad 8` or `Left Shift`.
+///
+/// CHIP-8 keys start from a layout preset, `layout` in the `[keypad]` section, and can then be rebound one at
+/// a time with `key.0` to `key.F`. Both can be set again in the ROM's section, which takes precedence, since
+/// many games only use a few keys that are awkward to reach on the grid.
+///
+/// Host keys can also play a `Macro`, bound with `macro.HOSTKEY = ...` in the same sections.
+///
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    pub keys: [Vec<Keycode>; 16],
+    pub hotkeys: Vec<(Keycode, Hotkey)>,
+    pub macros: Vec<(Keycode, Macro)>,
+}
+
+impl Keymap {
+    pub fn load(config: &Config, rom_section: &str, layout: &str) -> Result<Self, String> {
+        let preset = match layout {
+            "qwerty" => QWERTY,
+            "azerty" => AZERTY,
+            "qwertz" => QWERTZ,
+            "dvorak" => DVORAK,
+            "numpad" => NUMPAD,
+            _ => {
+                return Err(format!(
+                    "Unknown keypad layout `{}`, expected `qwerty`, `azerty`, `qwertz`, `dvorak` or `numpad`",
+                    layout
+                ))
+            }
+        };
+
+        let mut keys: [Vec<Keycode>; 16] = Default::default();
+        for (key, name) in keys.iter_mut().zip(preset) {
+            *key = vec![parse_key(name)?];
+        }
+        let mut macros: Vec<(Keycode, Macro)> = Vec::new();
+        for section in ["keypad", rom_section] {
+            for (name, value) in config.entries(section) {
+                if let Some(host_key) = name.strip_prefix("macro.") {
+                    let keycode = parse_key(host_key)?;
+                    macros.retain(|(bound, _)| *bound != keycode);
+                    macros.push((keycode, Macro::parse(value)?));
+                    continue;
+                }
+                let Some(index) = name.strip_prefix("key.") else {
+                    continue;
+                };
+                let index = u8::from_str_radix(index, 16)
+                    .ok()
+                    .filter(|index| *index < 16)
+                    .ok_or(format!(
+                        "Unknown CHIP-8 key `{}`, expected `key.0` to `key.F`",
+                        name
+                    ))?;
+                keys[index as usize] = parse_keys(value)?;
+            }
+        }
+
+        // A host key can only be one of a CHIP-8 key, a macro and a hotkey. Bindings from the config are
+        // rejected, while default hotkeys give way, e.g. `.` is key 6 in the Dvorak preset.
+        let chip8_key = |keycode: &Keycode| keys.iter().position(|bound| bound.contains(keycode));
+        if let Some((keycode, key)) = macros
+            .iter()
+            .find_map(|(keycode, _)| Some((keycode, chip8_key(keycode)?)))
+        {
+            return Err(format!(
+                "Key `{}` is bound to both CHIP-8 key {:X} and a macro",
+                keycode.name(),
+                key
+            ));
+        }
+        let binding = |keycode: &Keycode| match chip8_key(keycode) {
+            Some(key) => Some(format!("CHIP-8 key {:X}", key)),
+            None => macros
+                .iter()
+                .any(|(bound, _)| bound == keycode)
+                .then(|| "a macro".to_string()),
+        };
+        let mut hotkeys = Vec::new();
+        for hotkey in Hotkey::ALL {
+            let bound = match config.get("hotkeys", hotkey.name()) {
+                Some(value) => {
+                    let bound = parse_keys(value)?;
+                    if let Some((keycode, binding)) = bound
+                        .iter()
+                        .find_map(|keycode| Some((keycode, binding(keycode)?)))
+                    {
+                        return Err(format!(
+                            "Key `{}` is bound to both {} and the `{}` hotkey",
+                            keycode.name(),
+                            binding,
+                            hotkey.name()
+                        ));
+                    }
+                    bound
+                }
+                None => {
+                    let mut bound: Vec<Keycode> = hotkey
+                        .default_keys()
+                        .iter()
+                        .map(|name| parse_key(name))
+                        .collect::<Result<_, _>>()?;
+                    bound.retain(|keycode| match binding(keycode) {
+                        Some(binding) => {
+                            eprintln!(
+                                "Key `{}` is bound to {}, so it doesn't trigger the `{}` hotkey. \
+                                 Rebind it in the `[hotkeys]` section.",
+                                keycode.name(),
+                                binding,
+                                hotkey.name()
+                            );
+                            false
+                        }
+                        None => true,
+                    });
+                    bound
+                }
+            };
+            hotkeys.extend(bound.into_iter().map(|keycode| (keycode, hotkey)));
+        }
+        for (name, _) in config.entries("hotkeys") {
+            if Hotkey::from_name(name).is_none() {
+                return Err(format!("Unknown hotkey `{}`", name));
+            }
+        }
+
+        Ok(Self {
+            keys,
+            hotkeys,
+            macros,
+        })
+    }
+
+    ///
+    /// Returns the name of the first host key bound to each CHIP-8 key, or an empty name for unbound keys
+    ///
+    pub fn labels(&self) -> [String; 16] {
+        self.keys.clone().map(|bound| {
+            bound
+                .first()
+                .map(|keycode| keycode.name())
+                .unwrap_or_default()
+        })
+    }
+
+    pub fn hotkey(&self, keycode: Keycode) -> Option<Hotkey> {
+        self.hotkeys
+            .iter()
+            .find(|(bound, _)| *bound == keycode)
+            .map(|(_, hotkey)| *hotkey)
+    }
+}
+
+///
+/// Parses a comma-separated list of key names. An empty list leaves the key unbound.
+///
+fn parse_keys(value: &str) -> Result<Vec<Keycode>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(parse_key)
+        .collect()
+}
+
+///
+/// Parses a key name. The comma key is named `Comma`, since a comma separates keys in a list.
+///
+fn parse_key(name: &str) -> Result<Keycode, String> {
+    match name {
+        "Comma" => Ok(Keycode::Comma),
+        _ => Keycode::from_name(name).ok_or(format!("Unknown key `{}`", name)),
+    }
+}
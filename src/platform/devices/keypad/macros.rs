@@ -0,0 +1,216 @@
+use sdl2::keyboard::Keycode;
+
+use crate::chip8::{InputBuffer, KeyEvent};
+use crate::constants;
+
+///
+/// Macro
+///
+/// Something a host key does to the CHIP-8 keys other than pressing one of them. Macros are timed in frames,
+/// so a macro plays the same way on every run.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum Macro {
+    /// `autofire KEY RATE`: presses and releases a key `RATE` times per second while the host key is down
+    Autofire { key: u8, rate: f32 },
+    /// `toggle KEY`: each press of the host key holds the key down or lets it go
+    Toggle { key: u8 },
+    /// `sequence STEP...`: plays the steps in order when the host key is pressed
+    Sequence(Vec<Step>),
+}
+
+///
+/// One step of a sequence, written `KEYS:FRAMES`: the keys to hold, joined with `+`, or `-` for none, and
+/// how many frames to hold them for. E.g. `5+6:4` holds keys 5 and 6 for 4 frames.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Step {
+    /// One bit per key
+    pub keys: u16,
+    pub frames: u32,
+}
+
+impl Macro {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut words = value.split_whitespace();
+        let kind = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+
+        match (kind, args.as_slice()) {
+            ("autofire", [key, rate]) => {
+                let rate = rate
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|rate| *rate > 0.0 && *rate <= constants::FRAME_RATE as f32 / 2.0)
+                    .ok_or(format!(
+                        "Invalid autofire rate `{}`, expected up to {} presses per second",
+                        rate,
+                        constants::FRAME_RATE / 2
+                    ))?;
+                Ok(Self::Autofire {
+                    key: parse_chip8_key(key)?,
+                    rate,
+                })
+            }
+            ("toggle", [key]) => Ok(Self::Toggle {
+                key: parse_chip8_key(key)?,
+            }),
+            ("sequence", steps) if !steps.is_empty() => steps
+                .iter()
+                .map(|step| parse_step(step))
+                .collect::<Result<_, _>>()
+                .map(Self::Sequence),
+            _ => Err(format!(
+                "Invalid macro `{}`, expected `autofire KEY RATE`, `toggle KEY` or `sequence STEP...`",
+                value
+            )),
+        }
+    }
+}
+
+fn parse_step(step: &str) -> Result<Step, String> {
+    let (keys, frames) = step.split_once(':').ok_or(format!(
+        "Invalid sequence step `{}`, expected `KEYS:FRAMES`",
+        step
+    ))?;
+    let frames = frames
+        .parse::<u32>()
+        .ok()
+        .filter(|frames| *frames > 0)
+        .ok_or(format!(
+            "Invalid number of frames in sequence step `{}`",
+            step
+        ))?;
+    let keys = match keys {
+        "-" => 0,
+        _ => keys
+            .split('+')
+            .map(parse_chip8_key)
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .fold(0, |mask, key| mask | 1 << key),
+    };
+    Ok(Step { keys, frames })
+}
+
+fn parse_chip8_key(key: &str) -> Result<u8, String> {
+    u8::from_str_radix(key, 16)
+        .ok()
+        .filter(|key| *key < 16)
+        .ok_or(format!("Invalid CHIP-8 key `{}`, expected 0 to F", key))
+}
+
+///
+/// A macro in progress: an autofire whose host key is down, or a sequence that hasn't finished
+///
+#[derive(Debug)]
+struct Playing {
+    index: usize,
+    frame: u32,
+}
+
+///
+/// Macro Player
+///
+/// Plays the macros bound to host keys. Once per frame, it works out which CHIP-8 keys the macros hold down
+/// and queues key events for the ones that changed.
+///
+#[derive(Debug)]
+pub struct MacroPlayer {
+    macros: Vec<(Keycode, Macro)>,
+    playing: Vec<Playing>,
+    /// Keys held by toggles, one bit per key
+    toggled: u16,
+    /// Keys held by macros in the last frame, one bit per key
+    held: u16,
+}
+
+impl MacroPlayer {
+    pub fn new(macros: Vec<(Keycode, Macro)>) -> Self {
+        Self {
+            macros,
+            playing: Vec::new(),
+            toggled: 0,
+            held: 0,
+        }
+    }
+
+    ///
+    /// Handles a host key going down or up, if it's bound to a macro
+    ///
+    pub fn host_key(&mut self, keycode: Keycode, pressed: bool) {
+        let Some(index) = self.macros.iter().position(|(bound, _)| *bound == keycode) else {
+            return;
+        };
+
+        match (&self.macros[index].1, pressed) {
+            (Macro::Toggle { key }, true) => self.toggled ^= 1 << key,
+            // Pressing the host key again restarts a sequence
+            (Macro::Autofire { .. } | Macro::Sequence(_), true) => {
+                self.playing.retain(|playing| playing.index != index);
+                self.playing.push(Playing { index, frame: 0 });
+            }
+            (Macro::Autofire { .. }, false) => {
+                self.playing.retain(|playing| playing.index != index)
+            }
+            // Sequences play to the end whether the host key is held or not
+            _ => {}
+        }
+    }
+
+    ///
+    /// Stops every macro and forgets the keys they held, e.g. when the window loses focus and the host keys'
+    /// releases go elsewhere. The caller releases the CHIP-8 keys.
+    ///
+    pub fn release_all(&mut self) {
+        self.playing.clear();
+        self.toggled = 0;
+        self.held = 0;
+    }
+
+    ///
+    /// Advances the macros by a frame and queues the key events for it
+    ///
+    pub fn update(&mut self, keys: &mut InputBuffer, timestamp: u32) {
+        let mut held = self.toggled;
+        let macros = &self.macros;
+        self.playing.retain_mut(|playing| {
+            let keys = match &macros[playing.index].1 {
+                Macro::Autofire { key, rate } => {
+                    // Down for the first half of each period, up for the second
+                    let period = (constants::FRAME_RATE as f32 / rate).round().max(2.0) as u32;
+                    if playing.frame % period < period / 2 {
+                        1 << key
+                    } else {
+                        0
+                    }
+                }
+                Macro::Sequence(steps) => {
+                    let mut start = 0;
+                    let step = steps.iter().find(|step| {
+                        start += step.frames;
+                        playing.frame < start
+                    });
+                    match step {
+                        Some(step) => step.keys,
+                        None => return false,
+                    }
+                }
+                Macro::Toggle { .. } => 0,
+            };
+            held |= keys;
+            playing.frame += 1;
+            true
+        });
+
+        let changed = held ^ self.held;
+        for key in (0..16).filter(|key| changed & (1 << key) != 0) {
+            keys.push(KeyEvent {
+                key,
+                pressed: held & (1 << key) != 0,
+                timestamp,
+            });
+        }
+        self.held = held;
+    }
+}
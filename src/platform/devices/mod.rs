@@ -3,7 +3,9 @@ mod display;
 mod keypad;
 mod tape;
 
-pub use audio::Audio;
-pub use display::Display;
-pub use keypad::Keypad;
+pub use audio::{Audio, Pacing, Tone, WavWriter, Waveform};
+pub use display::{
+    write_screen, Charset, Display, Effects, Filter, Palette, RecordingFormat, ScaleMode,
+};
+pub use keypad::{Keymap, Keypad};
 pub use tape::Tape;
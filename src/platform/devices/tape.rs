@@ -1,12 +1,25 @@
+mod formats;
+
 extern crate sdl2;
-use std::{fs, time::Duration};
+use std::io::{self, Read};
+use std::{fs, path::Path, time::Duration};
+
+use log::debug;
 
-use crate::constants;
+use formats::RomFormat;
+
+use crate::{constants, utils};
 
 ///
 /// Tape
 ///
+/// Reads ROMs from a path, or from stdin when the path is `-`. A relative path that doesn't exist is looked
+/// up in the ROM folder. The `RomFormat` is detected from the extension or the contents.
+///
 pub struct Tape {
+    /// Folder relative paths are looked up in when they aren't found as given
+    pub folder: String,
+    pub name: String,
     pub rom: Vec<u8>,
     pub size: usize,
     pub baud: usize,
@@ -16,6 +29,8 @@ pub struct Tape {
 impl Tape {
     pub fn new() -> Self {
         Tape {
+            folder: constants::ROM_FOLDER.to_string(),
+            name: String::new(),
             rom: Vec::new(),
             size: 0,
             baud: 750,
@@ -23,16 +38,40 @@ impl Tape {
         }
     }
 
-    pub fn read(&mut self, filename: &String) {
-        let program_data = fs::read(format!("{}/{}", constants::ROM_FOLDER, filename).as_str())
-            .unwrap_or(Vec::new());
+    ///
+    /// Reads a ROM, which has to fit in the `max_size` bytes of memory after the start of the program
+    ///
+    pub fn read(&mut self, filename: &str, max_size: usize) -> Result<(), String> {
+        let data = if filename == "-" {
+            let mut data = Vec::new();
+            io::stdin()
+                .read_to_end(&mut data)
+                .map_err(|e| format!("Couldn't read ROM from stdin: {}", e))?;
+            data
+        } else {
+            let path = Path::new(filename);
+            let path = if path.is_relative() && !path.exists() {
+                Path::new(&self.folder).join(path)
+            } else {
+                path.to_path_buf()
+            };
+            fs::read(&path).map_err(|e| format!("Couldn't read ROM `{}`: {}", path.display(), e))?
+        };
+
+        let format = RomFormat::detect(filename, &data);
+        debug!("Reading {} as a {} ROM", filename, format.name());
+        let program_data = format
+            .decode(data)
+            .map_err(|e| format!("Couldn't read ROM `{}`: {}", filename, e))?;
         let size = program_data.len();
-        if size >= constants::MAX_ROM_SIZE as usize {
-            panic!(
-                "ROM is too big! {}B is greater than the {}B max size",
-                size,
-                constants::MAX_ROM_SIZE,
-            );
+        if size == 0 {
+            return Err(format!("ROM `{}` is empty", filename));
+        }
+        if size > max_size {
+            return Err(format!(
+                "ROM `{}` is too big! {}B is greater than the {}B max size",
+                filename, size, max_size,
+            ));
         }
 
         if self.sim_timing && self.baud > 0 {
@@ -40,7 +79,19 @@ impl Tape {
             std::thread::sleep(wait_len);
         }
 
+        self.name = utils::rom_name(filename);
         self.size = size;
         self.rom = program_data;
+        Ok(())
+    }
+
+    ///
+    /// Loads a ROM that's already in memory, such as the one a recompiled build carries
+    ///
+    #[cfg(feature = "recompiled")]
+    pub fn load(&mut self, filename: &str, rom: &[u8]) {
+        self.name = utils::rom_name(filename);
+        self.size = rom.len();
+        self.rom = rom.to_vec();
     }
 }
@@ -0,0 +1,280 @@
+use std::path::Path;
+
+use crate::constants;
+
+///
+/// ROM Format
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomFormat {
+    /// The bytes of the program as they're loaded into memory
+    Raw,
+    /// Bytes written as hex digits separated by whitespace, e.g. `00E0 A22A`. A word can hold several bytes.
+    HexText,
+    /// Intel HEX records, e.g. `:0400000000E0A22A50`
+    IntelHex,
+}
+
+impl RomFormat {
+    ///
+    /// Works out the format of a ROM from its extension, or from its contents when the extension doesn't say
+    ///
+    pub fn detect(path: &str, data: &[u8]) -> Self {
+        let extension = Path::new(path)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        if let Some("ch8" | "c8" | "sc8" | "xo8") = extension.as_deref() {
+            return Self::Raw;
+        }
+
+        let Ok(text) = std::str::from_utf8(data) else {
+            return Self::Raw;
+        };
+        let lines: Vec<&str> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        if lines.is_empty() {
+            Self::Raw
+        } else if lines.iter().all(|line| line.starts_with(':')) {
+            Self::IntelHex
+        } else if is_hex_text(text) {
+            Self::HexText
+        } else {
+            Self::Raw
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Raw => "raw",
+            Self::HexText => "hex text",
+            Self::IntelHex => "Intel HEX",
+        }
+    }
+
+    ///
+    /// Decodes a ROM into the bytes of the program
+    ///
+    pub fn decode(&self, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Raw => Ok(data),
+            Self::HexText => decode_hex_text(&String::from_utf8_lossy(&data)),
+            Self::IntelHex => decode_intel_hex(&String::from_utf8_lossy(&data)),
+        }
+    }
+}
+
+///
+/// Whether text reads as hex text: hex digits split into words of whole bytes by whitespace. A raw ROM made only
+/// of those bytes can still pass, which is why the extensions of raw ROMs skip detection.
+///
+fn is_hex_text(text: &str) -> bool {
+    text.chars()
+        .all(|c| c.is_ascii_hexdigit() || c.is_ascii_whitespace())
+        && text.chars().any(|c| c.is_ascii_whitespace())
+        && text.split_whitespace().all(|word| word.len() % 2 == 0)
+}
+
+fn decode_hex_text(text: &str) -> Result<Vec<u8>, String> {
+    let mut program = Vec::new();
+    for word in text.split_whitespace() {
+        if word.len() % 2 != 0 {
+            return Err(format!(
+                "Invalid hex `{}`, expected whole bytes of two digits each",
+                word
+            ));
+        }
+        program.extend(decode_hex_bytes(word)?);
+    }
+    Ok(program)
+}
+
+///
+/// Decodes Intel HEX records. Addresses are memory addresses when none of them are below the start of the
+/// program, and offsets into the program otherwise. Gaps between records are filled with zeros.
+///
+fn decode_intel_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut records = Vec::new();
+    let mut base = 0;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: &str| format!("Intel HEX line {}: {}", number + 1, message);
+
+        let bytes = line
+            .strip_prefix(':')
+            .ok_or_else(|| error("expected a record starting with `:`"))
+            .and_then(|record| decode_hex_bytes(record).map_err(|e| error(&e)))?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(error("record length doesn't match its byte count"));
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(error("bad checksum"));
+        }
+
+        let address = base + u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+        let data = &bytes[4..bytes.len() - 1];
+        let segment = || match data {
+            [high, low] => Ok(u16::from_be_bytes([*high, *low]) as usize),
+            _ => Err(error("expected a 2-byte address")),
+        };
+        match bytes[3] {
+            0x00 => {
+                if address + data.len() > constants::SYSTEM_RAM as usize {
+                    return Err(error(&format!("address {:#X} is outside of RAM", address)));
+                }
+                records.push((address, data.to_vec()));
+            }
+            0x01 => break,
+            0x02 => base = segment()? << 4,
+            0x04 => base = segment()? << 16,
+            // Start addresses don't matter, the program always starts at 0x200
+            0x03 | 0x05 => {}
+            kind => return Err(error(&format!("unsupported record type {:02X}", kind))),
+        }
+    }
+
+    let start = records
+        .iter()
+        .map(|(address, _)| *address)
+        .min()
+        .unwrap_or(0);
+    let origin = if start >= constants::PROGRAM_START_ADDR as usize {
+        constants::PROGRAM_START_ADDR as usize
+    } else {
+        0
+    };
+    let mut program = Vec::new();
+    for (address, data) in records {
+        let offset = address - origin;
+        if program.len() < offset + data.len() {
+            program.resize(offset + data.len(), 0);
+        }
+        program[offset..offset + data.len()].copy_from_slice(&data);
+    }
+    Ok(program)
+}
+
+fn decode_hex_bytes(digits: &str) -> Result<Vec<u8>, String> {
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            digits
+                .get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or(format!("Invalid hex `{}`", digits))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intel_hex(records: &[&str]) -> Result<Vec<u8>, String> {
+        RomFormat::IntelHex.decode(records.join("\n").into_bytes())
+    }
+
+    #[test]
+    fn raw_extensions_skip_detection() {
+        assert_eq!(
+            RomFormat::detect("PONG.ch8", b"00E0 A22A\n"),
+            RomFormat::Raw
+        );
+        assert_eq!(
+            RomFormat::detect("roms/PONG.SC8", b":00000001FF"),
+            RomFormat::Raw
+        );
+    }
+
+    #[test]
+    fn contents_are_detected() {
+        let raw = [0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0xD0, 0x15];
+        assert_eq!(RomFormat::detect("PONG", &raw), RomFormat::Raw);
+        assert_eq!(
+            RomFormat::detect("-", b"00E0 A22A\n600C\n"),
+            RomFormat::HexText
+        );
+        assert_eq!(
+            RomFormat::detect("pong.hex", b":0402000000E0A22A4E\n:00000001FF\n"),
+            RomFormat::IntelHex
+        );
+    }
+
+    #[test]
+    fn raw_roms_of_hex_digit_bytes_stay_raw() {
+        // `3AB0`, `4A30`, `1200`-like bytes that happen to be ASCII hex digits, with nothing splitting them
+        assert_eq!(RomFormat::detect("GAME", b"3AB04A3012"), RomFormat::Raw);
+        // Split by whitespace, but not into whole bytes
+        assert_eq!(RomFormat::detect("GAME", b"3AB 04A3\n"), RomFormat::Raw);
+    }
+
+    #[test]
+    fn hex_text_words_can_hold_several_bytes() {
+        let program = RomFormat::HexText.decode(b"00E0A22A\n  60 0C\tD015\n".to_vec());
+        assert_eq!(
+            program,
+            Ok(vec![0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0xD0, 0x15])
+        );
+    }
+
+    #[test]
+    fn hex_text_rejects_odd_length_words() {
+        assert!(RomFormat::HexText.decode(b"00E0 A22".to_vec()).is_err());
+        assert!(RomFormat::HexText.decode(b"00E0 G22A".to_vec()).is_err());
+    }
+
+    #[test]
+    fn intel_hex_at_memory_addresses_starts_at_the_program() {
+        let program = intel_hex(&[":0402000000E0A22A4E", ":020204001204E2", ":00000001FF"]);
+        assert_eq!(program, Ok(vec![0x00, 0xE0, 0xA2, 0x2A, 0x12, 0x04]));
+    }
+
+    #[test]
+    fn intel_hex_below_the_program_is_offsets_with_gaps_filled() {
+        let program = intel_hex(&[":0200000000E01E", ":020004001204E4", ":00000001FF"]);
+        assert_eq!(program, Ok(vec![0x00, 0xE0, 0x00, 0x00, 0x12, 0x04]));
+    }
+
+    #[test]
+    fn intel_hex_ignores_records_after_the_end() {
+        let program = intel_hex(&[":0200000000E01E", ":00000001FF", ":0100000055AA"]);
+        assert_eq!(program, Ok(vec![0x00, 0xE0]));
+    }
+
+    #[test]
+    fn intel_hex_segment_addresses() {
+        // Segment 0x20 puts offset 2 at 0x202
+        let program = intel_hex(&[":020000020020DC", ":01000200AB52", ":00000001FF"]);
+        assert_eq!(program, Ok(vec![0x00, 0x00, 0xAB]));
+
+        // A linear address replaces the segment
+        let records = [
+            ":020000020020DC",
+            ":020000040000FA",
+            ":0102000055A8",
+            ":00000001FF",
+        ];
+        let program = intel_hex(&records);
+        assert_eq!(program, Ok(vec![0x55]));
+
+        let error = intel_hex(&[":020000040001F9", ":0100000055AA"]).unwrap_err();
+        assert!(error.contains("outside of RAM"), "{}", error);
+    }
+
+    #[test]
+    fn intel_hex_rejects_bad_records() {
+        let error = intel_hex(&[":0402000000E0A22A4F"]).unwrap_err();
+        assert!(error.contains("bad checksum"), "{}", error);
+
+        let error = intel_hex(&[":0502000000E0A22A4D"]).unwrap_err();
+        assert!(error.contains("byte count"), "{}", error);
+
+        let error = intel_hex(&[":0200000000E01E", "0200000000E01E"]).unwrap_err();
+        assert!(error.starts_with("Intel HEX line 2"), "{}", error);
+    }
+}
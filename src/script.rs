@@ -0,0 +1,379 @@
+use std::fmt;
+use std::fs;
+
+use crate::chip8::{Cpu, FrameClock, KeyEvent};
+use crate::constants;
+use crate::platform::{write_screen, Palette};
+
+/// Frames an `until` waits before the script fails, one minute of emulated time
+const UNTIL_TIMEOUT_FRAMES: u32 = 60 * constants::FRAME_RATE as u32;
+
+///
+/// Condition checked by `until` and `assert`
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Condition {
+    /// `pixel X Y`: the pixel is lit
+    Pixel { x: usize, y: usize },
+    /// `mem ADDRESS == VALUE` or `mem ADDRESS != VALUE`
+    Memory {
+        address: u16,
+        equal: bool,
+        value: u8,
+    },
+}
+
+impl Condition {
+    fn parse(args: &[&str]) -> Result<Self, String> {
+        match args {
+            ["pixel", x, y] => Ok(Self::Pixel {
+                x: parse_number(x)? as usize,
+                y: parse_number(y)? as usize,
+            }),
+            ["mem", address, op, value] => {
+                let address = parse_number(address)?;
+                if address >= constants::SYSTEM_RAM as u32 {
+                    return Err(format!("Address `{:#X}` is outside of RAM", address));
+                }
+                let equal = match *op {
+                    "==" => true,
+                    "!=" => false,
+                    _ => return Err(format!("Unknown operator `{}`, expected `==` or `!=`", op)),
+                };
+                let value = u8::try_from(parse_number(value)?)
+                    .map_err(|_| format!("Value `{}` doesn't fit in a byte", value))?;
+                Ok(Self::Memory {
+                    address: address as u16,
+                    equal,
+                    value,
+                })
+            }
+            _ => Err("Expected `pixel X Y` or `mem ADDRESS == VALUE`".to_string()),
+        }
+    }
+
+    fn check(&self, cpu: &Cpu) -> bool {
+        match *self {
+            Self::Pixel { x, y } => {
+                x < cpu.vram.get_screen_width()
+                    && y < cpu.vram.get_screen_height()
+                    && cpu.vram.read_visible(x, y) != 0
+            }
+            Self::Memory {
+                address,
+                equal,
+                value,
+            } => (cpu.read_memory(address) == value) == equal,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Pixel { x, y } => write!(f, "pixel {} {}", x, y),
+            Self::Memory {
+                address,
+                equal,
+                value,
+            } => write!(
+                f,
+                "mem {:#05X} {} {}",
+                address,
+                if *equal { "==" } else { "!=" },
+                value
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Command {
+    Wait(u32),
+    Hold(u8, u32),
+    Until(Condition),
+    Assert(Condition),
+    Screenshot(String),
+    Exit,
+}
+
+///
+/// Script
+///
+/// Drives the keypad frame by frame from a text file, one command per line, with `#` starting a comment:
+///
+/// - `wait FRAMES` runs frames without touching the keys
+/// - `press KEY [TIMES]` taps a key, down for one frame and up for the next, once or several times
+/// - `hold KEY FRAMES` holds a key down for a number of frames
+/// - `until pixel X Y` or `until mem ADDRESS == VALUE` runs frames until the condition holds, failing after
+///   a minute of emulated time. `!=` works too.
+/// - `assert pixel X Y` or `assert mem ADDRESS == VALUE` checks a condition and records a failure if it
+///   doesn't hold
+/// - `screenshot FILE` saves the screen as a PNG, or as text if the file ends in `.txt`
+/// - `exit` ends the script
+///
+/// Keys are hex digits, and numbers can be written in decimal or with a `0x` prefix.
+///
+#[derive(Debug)]
+pub struct Script {
+    commands: Vec<(usize, Command)>,
+    index: usize,
+    /// Frames run by the current command so far
+    frames: u32,
+    /// Whether the script got to its end, an `exit` or a timeout, rather than the ROM quitting first
+    finished: bool,
+    pub assertions: u32,
+    pub passed: u32,
+    /// Failed assertions and anything else that went wrong, by line
+    pub failures: Vec<String>,
+}
+
+impl Script {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read script `{}`: {}", path, e))?;
+        Self::parse(path, &text)
+    }
+
+    fn parse(path: &str, text: &str) -> Result<Self, String> {
+        let mut commands = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let parsed =
+                parse_command(&words).map_err(|e| format!("{}:{}: {}", path, number + 1, e))?;
+            commands.extend(parsed.into_iter().map(|command| (number + 1, command)));
+        }
+
+        Ok(Self {
+            commands,
+            index: 0,
+            frames: 0,
+            finished: false,
+            assertions: 0,
+            passed: 0,
+            failures: Vec::new(),
+        })
+    }
+
+    ///
+    /// Runs the script up to the next frame, queuing key events for it at `timestamp`. The runner applies them
+    /// along with any others, so `Fx0A` sees the presses. Returns false once the script is over.
+    ///
+    pub fn begin_frame(&mut self, cpu: &mut Cpu, timestamp: u32) -> bool {
+        let key_event = |key, pressed| KeyEvent {
+            key,
+            pressed,
+            timestamp,
+        };
+        while let Some((line, command)) = self.commands.get(self.index).cloned() {
+            match command {
+                Command::Wait(frames) => {
+                    if self.frames < frames {
+                        self.frames += 1;
+                        return true;
+                    }
+                }
+                Command::Hold(key, frames) => {
+                    if self.frames < frames {
+                        if self.frames == 0 {
+                            cpu.keys.push(key_event(key, true));
+                        }
+                        self.frames += 1;
+                        return true;
+                    }
+                    cpu.keys.push(key_event(key, false));
+                }
+                Command::Until(condition) => {
+                    if !condition.check(cpu) {
+                        if self.frames == UNTIL_TIMEOUT_FRAMES {
+                            self.failures.push(format!(
+                                "line {}: timed out waiting for {}",
+                                line, condition
+                            ));
+                            self.finished = true;
+                            return false;
+                        }
+                        self.frames += 1;
+                        return true;
+                    }
+                }
+                Command::Assert(condition) => {
+                    self.assertions += 1;
+                    if condition.check(cpu) {
+                        self.passed += 1;
+                    } else {
+                        self.failures
+                            .push(format!("line {}: assertion failed: {}", line, condition));
+                    }
+                }
+                Command::Screenshot(path) => {
+                    let palette = &Palette::builtin()[0];
+                    if let Err(e) = write_screen(&cpu.vram, palette, constants::VIDEO_SCALE, &path)
+                    {
+                        self.failures.push(format!("line {}: {}", line, e));
+                    }
+                }
+                Command::Exit => {
+                    self.finished = true;
+                    return false;
+                }
+            }
+            self.index += 1;
+            self.frames = 0;
+        }
+        self.finished = true;
+        false
+    }
+
+    ///
+    /// Runs the script headless, as fast as possible, until it's over or the ROM quits
+    ///
+    pub fn run_headless(&mut self, cpu: &mut Cpu, mut frame_clock: FrameClock) {
+        let mut frame: u64 = 0;
+        loop {
+            // Key events are timestamped on the emulated clock
+            let now = (frame * 1_000 / constants::FRAME_RATE as u64) as u32;
+            if cpu.quit_flag || !self.begin_frame(cpu, now) {
+                break;
+            }
+            cpu.keys.apply_events(now);
+            cpu.run(frame_clock.next_frame());
+            cpu.tick_timers();
+            cpu.vram.end_frame();
+            frame += 1;
+        }
+    }
+
+    ///
+    /// Prints the outcome of the assertions. Returns true if there were no failures and the script got to its
+    /// end, since the commands left when the ROM quits early didn't run.
+    ///
+    pub fn report(&self) -> bool {
+        for failure in &self.failures {
+            eprintln!("{}", failure);
+        }
+        if let Some((line, _)) = self.unfinished() {
+            eprintln!("line {}: the ROM quit before the script finished", line);
+        }
+        println!("{} of {} assertions passed", self.passed, self.assertions);
+        self.failures.is_empty() && self.unfinished().is_none()
+    }
+
+    ///
+    /// Returns the command the script stopped at, if it didn't get to its end
+    ///
+    fn unfinished(&self) -> Option<&(usize, Command)> {
+        match self.finished {
+            true => None,
+            false => self.commands.get(self.index),
+        }
+    }
+}
+
+fn parse_command(words: &[&str]) -> Result<Vec<Command>, String> {
+    let commands = match words {
+        ["wait", frames] => vec![Command::Wait(parse_number(frames)?)],
+        ["press", key] => vec![Command::Hold(parse_key(key)?, 1), Command::Wait(1)],
+        ["press", key, times] => {
+            let key = parse_key(key)?;
+            (0..parse_number(times)?)
+                .flat_map(|_| [Command::Hold(key, 1), Command::Wait(1)])
+                .collect()
+        }
+        ["hold", key, frames] => vec![Command::Hold(parse_key(key)?, parse_number(frames)?)],
+        ["until", condition @ ..] => vec![Command::Until(Condition::parse(condition)?)],
+        ["assert", condition @ ..] => vec![Command::Assert(Condition::parse(condition)?)],
+        ["screenshot", path] => vec![Command::Screenshot(path.to_string())],
+        ["exit"] => vec![Command::Exit],
+        _ => return Err(format!("Unknown command `{}`", words.join(" "))),
+    };
+    Ok(commands)
+}
+
+fn parse_key(key: &str) -> Result<u8, String> {
+    u8::from_str_radix(key, 16)
+        .ok()
+        .filter(|key| *key < 16)
+        .ok_or(format!("Invalid key `{}`, expected 0 to F", key))
+}
+
+fn parse_number(number: &str) -> Result<u32, String> {
+    match number.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => number.parse(),
+    }
+    .map_err(|_| format!("Invalid number `{}`", number))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::KeyWait;
+
+    /// Waits for a key with `Fx0A`, stores it at 0x300 and loops
+    const WAIT_FOR_KEY: [u8; 8] = [0xF0, 0x0A, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06];
+
+    const PRESS_5: &str = "
+        wait 2
+        press 5
+        wait 2
+        assert mem 0x300 == 5
+    ";
+
+    fn cpu(key_wait: KeyWait) -> Cpu {
+        let mut cpu = Cpu::new(false);
+        cpu.key_wait = key_wait;
+        cpu.load_program(WAIT_FOR_KEY.to_vec());
+        cpu
+    }
+
+    #[test]
+    fn press_resumes_key_wait_headless() {
+        for key_wait in [KeyWait::Press, KeyWait::Release] {
+            let mut script = Script::parse("test", PRESS_5).unwrap();
+            let mut cpu = cpu(key_wait);
+            script.run_headless(&mut cpu, FrameClock::new(600));
+
+            assert_eq!(cpu.read_memory(0x300), 5, "{:?}", key_wait);
+            assert!(script.report());
+        }
+    }
+
+    #[test]
+    fn press_resumes_key_wait_in_the_window() {
+        for key_wait in [KeyWait::Press, KeyWait::Release] {
+            let mut script = Script::parse("test", PRESS_5).unwrap();
+            let mut cpu = cpu(key_wait);
+            // As the main loop runs frames, on SDL's clock
+            let mut now = 1_000;
+            while script.begin_frame(&mut cpu, now) {
+                cpu.keys.apply_events(now);
+                cpu.run(10);
+                cpu.tick_timers();
+                cpu.vram.end_frame();
+                now += 16;
+            }
+
+            assert_eq!(cpu.read_memory(0x300), 5, "{:?}", key_wait);
+            assert!(script.report());
+        }
+    }
+
+    #[test]
+    fn quitting_early_fails_the_script() {
+        let mut script = Script::parse("test", "wait 5\nassert mem 0x300 == 0").unwrap();
+        let mut cpu = cpu(KeyWait::Press);
+        // 00FD, exit
+        cpu.load_program(vec![0x00, 0xFD]);
+        cpu.schip_mode = true;
+        script.run_headless(&mut cpu, FrameClock::new(600));
+
+        assert!(cpu.quit_flag);
+        assert_eq!(script.assertions, 0);
+        assert!(!script.report());
+    }
+}
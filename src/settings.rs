@@ -0,0 +1,461 @@
+use std::env;
+
+use crate::chip8::{AntiFlicker, Cpu, FrameClock, KeyWait};
+use crate::config::{self, Config};
+use crate::constants;
+use crate::platform::{Tone, Waveform};
+
+///
+/// One setting: where it's read from and what it does
+///
+struct Setting {
+    /// Section of the config file. In a ROM's section, the key is used on its own.
+    section: &'static str,
+    /// Key in the config file, also the option name with `-` for `_`
+    key: &'static str,
+    env: &'static str,
+    /// Placeholder for the value in `--help`
+    value: &'static str,
+    default: Option<&'static str>,
+    help: &'static str,
+}
+
+const SETTINGS: &[Setting] = &[
+    Setting {
+        section: "emulator",
+        key: "clock",
+        env: "CHIP8_CLOCK",
+        value: "HZ",
+        default: Some("120"),
+        help: "Instructions executed per second",
+    },
+    Setting {
+        section: "emulator",
+        key: "quirks",
+        env: "CHIP8_QUIRKS",
+        value: "chip8|schip",
+        default: Some("chip8"),
+        help: "Run shifts, jumps, loads and sprite collisions as on CHIP-8 or S-CHIP",
+    },
+    Setting {
+        section: "emulator",
+        key: "key_wait",
+        env: "CHIP8_KEY_WAIT",
+        value: "press|release",
+        default: Some("release"),
+        help: "Whether `LD Vx, K` resumes when the key is pressed or released",
+    },
+    Setting {
+        section: "emulator",
+        key: "pacing",
+        env: "CHIP8_PACING",
+        value: "timer|audio",
+        default: Some("timer"),
+        help: "Pace frames with the system clock or the audio device",
+    },
+    Setting {
+        section: "emulator",
+        key: "rom_folder",
+        env: "CHIP8_ROM_FOLDER",
+        value: "DIR",
+        default: Some(constants::ROM_FOLDER),
+        help: "Folder relative ROM paths are looked up in when they aren't found as given",
+    },
+    Setting {
+        section: "display",
+        key: "scale",
+        env: "CHIP8_SCALE",
+        value: "N",
+        default: Some("12"),
+        help: "Initial window size, as a multiple of the screen size",
+    },
+    Setting {
+        section: "display",
+        key: "scale_mode",
+        env: "CHIP8_SCALE_MODE",
+        value: "integer|fit",
+        default: Some("integer"),
+        help: "Scale the screen by whole numbers or as large as fits",
+    },
+    Setting {
+        section: "display",
+        key: "palette",
+        env: "CHIP8_PALETTE",
+        value: "NAME",
+        default: None,
+        help: "Built-in or custom palette [default: amber]",
+    },
+    Setting {
+        section: "display",
+        key: "filter",
+        env: "CHIP8_FILTER",
+        value: "NAME",
+        default: Some("nearest"),
+        help: "Upscaling filter: nearest, scale2x, scale3x, epx or xbr",
+    },
+    Setting {
+        section: "display",
+        key: "effects",
+        env: "CHIP8_EFFECTS",
+        value: "LIST",
+        default: Some(""),
+        help: "Comma-separated effects: phosphor, scanlines, grid, led, bloom, curvature",
+    },
+    Setting {
+        section: "display",
+        key: "anti_flicker",
+        env: "CHIP8_ANTI_FLICKER",
+        value: "off|hold|blend",
+        default: Some("off"),
+        help: "Sprite flicker reduction",
+    },
+    Setting {
+        section: "display",
+        key: "renderer",
+        env: "CHIP8_RENDERER",
+        value: "gpu|software",
+        default: Some("gpu"),
+        help: "Draw with the GPU when there's one, or with SDL's software renderer",
+    },
+    Setting {
+        section: "display",
+        key: "virtual_keypad",
+        env: "CHIP8_VIRTUAL_KEYPAD",
+        value: "on|off",
+        default: Some("off"),
+        help: "Show the on-screen keypad at startup",
+    },
+    Setting {
+        section: "display",
+        key: "screenshot_text",
+        env: "CHIP8_SCREENSHOT_TEXT",
+        value: "ascii|unicode",
+        default: Some("unicode"),
+        help: "Characters used by the text version of screenshots",
+    },
+    Setting {
+        section: "display",
+        key: "recording_format",
+        env: "CHIP8_RECORDING_FORMAT",
+        value: "gif|y4m",
+        default: Some("gif"),
+        help: "Format of screen recordings",
+    },
+    Setting {
+        section: "audio",
+        key: "waveform",
+        env: "CHIP8_WAVEFORM",
+        value: "NAME",
+        default: Some("square"),
+        help: "Beep waveform: square, pulse, triangle, sine or noise",
+    },
+    Setting {
+        section: "audio",
+        key: "tone",
+        env: "CHIP8_TONE_HZ",
+        value: "HZ",
+        default: Some("440"),
+        help: "Beep frequency",
+    },
+    Setting {
+        section: "audio",
+        key: "volume",
+        env: "CHIP8_VOLUME",
+        value: "0-100",
+        default: Some("25"),
+        help: "Beep volume",
+    },
+    Setting {
+        section: "keypad",
+        key: "layout",
+        env: "CHIP8_LAYOUT",
+        value: "NAME",
+        default: Some("qwerty"),
+        help: "Keypad preset: qwerty, azerty, qwertz, dvorak or numpad",
+    },
+    Setting {
+        section: "debug",
+        key: "trace",
+        env: "CHIP8_TRACE_FILE",
+        value: "FILE",
+        default: None,
+        help: "Record an execution trace, for `cfg` and `recompile`",
+    },
+    Setting {
+        section: "debug",
+        key: "coverage",
+        env: "CHIP8_COVERAGE_FILE",
+        value: "FILE",
+        default: None,
+        help: "Write a coverage map of RAM on exit",
+    },
+    Setting {
+        section: "debug",
+        key: "profile",
+        env: "CHIP8_PROFILE_FILE",
+        value: "FILE",
+        default: None,
+        help: "Write a profiler report on exit",
+    },
+    Setting {
+        section: "debug",
+        key: "script",
+        env: "CHIP8_SCRIPT",
+        value: "FILE",
+        default: None,
+        help: "Drive the keypad with an input script",
+    },
+];
+
+///
+/// Settings
+///
+/// Every setting is resolved in layers, each overriding the one before: the built-in default, the config
+/// file, the env var, the ROM's section of the config file, and the command-line option. The config file
+/// itself is `./chip8.ini`, or the one given with `--config` or the `CHIP8_CONFIG` env var.
+///
+#[derive(Debug)]
+pub struct Settings {
+    pub rom: String,
+    pub config: Config,
+    pub rom_section: String,
+    /// Value of each setting that has one, with where it came from, in the same order as `SETTINGS`
+    values: Vec<Option<(String, String)>>,
+}
+
+impl Settings {
+    ///
+    /// Resolves the settings from command-line arguments, without the program name
+    ///
+    pub fn load(args: &[String]) -> Result<Self, String> {
+        let mut config_filename =
+            env::var("CHIP8_CONFIG").unwrap_or(constants::CONFIG_FILE.to_string());
+        let mut rom = None;
+        let mut options = Vec::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let Some(option) = arg.strip_prefix("--") else {
+                if rom.is_some() {
+                    return Err(format!("Unexpected argument `{}`\n\n{}", arg, usage()));
+                }
+                rom = Some(arg.clone());
+                continue;
+            };
+
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name, value.to_string()),
+                None => {
+                    let value = args
+                        .next()
+                        .ok_or(format!("Option `--{}` needs a value", option))?;
+                    (option, value.clone())
+                }
+            };
+            if name == "config" {
+                config_filename = value;
+                continue;
+            }
+            let index = SETTINGS
+                .iter()
+                .position(|setting| setting.key == name.replace('-', "_"))
+                .ok_or(format!("Unknown option `--{}`\n\n{}", name, usage()))?;
+            options.push((index, value, format!("--{}", name)));
+        }
+        // A recompiled build carries the ROM it was compiled from, and plays it unless given another one
+        #[cfg(feature = "recompiled")]
+        let rom = rom.or(Some(crate::recompiled::ROM_NAME.to_string()));
+        let rom = rom.ok_or(format!("Missing ROM_FILENAME\n\n{}", usage()))?;
+
+        let config = Config::load(&config_filename)?;
+        let rom_section = config::rom_section(&rom);
+
+        let mut values = Vec::new();
+        for setting in SETTINGS {
+            let from_file = |section: &str| {
+                config.get(section, setting.key).map(|value| {
+                    let origin =
+                        format!("`{}` in [{}] of {}", setting.key, section, config_filename);
+                    (value.to_string(), origin)
+                })
+            };
+            let value = from_file(&rom_section)
+                .or_else(|| {
+                    env::var(setting.env)
+                        .ok()
+                        .map(|value| (value, setting.env.to_string()))
+                })
+                .or_else(|| from_file(setting.section))
+                .or_else(|| {
+                    setting
+                        .default
+                        .map(|value| (value.to_string(), "the default".to_string()))
+                });
+            values.push(value);
+        }
+        for (index, value, origin) in options {
+            values[index] = Some((value, origin));
+        }
+
+        Ok(Self {
+            rom,
+            config,
+            rom_section,
+            values,
+        })
+    }
+
+    ///
+    /// Resolves the settings for a ROM without any command-line options, as the tools do
+    ///
+    pub fn for_rom(rom: &str) -> Result<Self, String> {
+        Self::load(&[rom.to_string()])
+    }
+
+    ///
+    /// Returns the value of a setting, if it has one
+    ///
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.value(key).map(|(value, _)| value.as_str())
+    }
+
+    ///
+    /// Parses the value of a setting that has a default. `expected` describes valid values for the error.
+    ///
+    pub fn parse<T>(
+        &self,
+        key: &str,
+        expected: &str,
+        parse: impl FnOnce(&str) -> Option<T>,
+    ) -> Result<T, String> {
+        let (value, origin) = self
+            .value(key)
+            .unwrap_or_else(|| panic!("Setting `{}` has no default", key));
+        parse(value).ok_or(format!(
+            "Invalid {} `{}` from {}, expected {}",
+            key.replace('_', " "),
+            value,
+            origin,
+            expected
+        ))
+    }
+
+    fn value(&self, key: &str) -> Option<&(String, String)> {
+        let index = SETTINGS
+            .iter()
+            .position(|setting| setting.key == key)
+            .unwrap_or_else(|| panic!("Unknown setting `{}`", key));
+        self.values[index].as_ref()
+    }
+
+    ///
+    /// Instructions to run per second. It's run in batches of whole frames, so it has to be at least 60.
+    ///
+    pub fn clock(&self) -> Result<usize, String> {
+        self.parse(
+            "clock",
+            &format!("a whole number of at least {}", constants::FRAME_RATE),
+            |value| {
+                value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|clock| *clock >= constants::FRAME_RATE)
+            },
+        )
+    }
+
+    ///
+    /// Splits the clock into instructions to run per 60 Hz frame
+    ///
+    pub fn frame_clock(&self) -> Result<FrameClock, String> {
+        Ok(FrameClock::new(self.clock()?))
+    }
+
+    ///
+    /// Creates a CPU with the quirks, `Fx0A` behavior and anti-flicker mode from the settings
+    ///
+    pub fn cpu(&self) -> Result<Cpu, String> {
+        let schip_mode = self.parse("quirks", "`chip8` or `schip`", |value| match value {
+            "chip8" => Some(false),
+            "schip" => Some(true),
+            _ => None,
+        })?;
+        let mut cpu = Cpu::new(schip_mode);
+        cpu.key_wait = self.parse("key_wait", "`press` or `release`", KeyWait::from_name)?;
+        cpu.vram.anti_flicker = self.parse(
+            "anti_flicker",
+            "`off`, `hold` or `blend`",
+            AntiFlicker::from_name,
+        )?;
+        Ok(cpu)
+    }
+
+    pub fn tone(&self) -> Result<Tone, String> {
+        Ok(Tone {
+            waveform: self.parse(
+                "waveform",
+                "`square`, `pulse`, `triangle`, `sine` or `noise`",
+                Waveform::from_name,
+            )?,
+            frequency: self.parse("tone", "a frequency in Hz", |value| {
+                value.parse::<f32>().ok().filter(|hz| *hz > 0.0)
+            })?,
+            volume: self.parse("volume", "0 to 100", |value| {
+                value.parse::<u8>().ok().filter(|percent| *percent <= 100)
+            })? as f32
+                / 100.0,
+        })
+    }
+}
+
+fn usage() -> String {
+    "Usage: rust-chip8 [OPTIONS] ROM_FILENAME\n       rust-chip8 audio|bench|cfg|recompile|script ...\n\nRun with --help to list the options.".to_string()
+}
+
+///
+/// Returns the text shown by `--help`
+///
+pub fn help() -> String {
+    let mut text = String::from(
+        "Usage: rust-chip8 [OPTIONS] ROM_FILENAME\n       rust-chip8 audio|bench|cfg|recompile|script ...\n\n",
+    );
+    text.push_str(
+        "Each setting is read from its default, the config file, its env var, the ROM's section of the\n\
+         config file and its option, each overriding the one before. The ROM's section is [rom.NAME],\n\
+         named after its file name without folder or extension, e.g. [rom.PONG] for roms/PONG.ch8.\n\n\
+         Options:\n",
+    );
+
+    let mut lines = vec![(
+        "--config FILE".to_string(),
+        format!(
+            "Config file [default: {}] [env: CHIP8_CONFIG]",
+            constants::CONFIG_FILE
+        ),
+    )];
+    for setting in SETTINGS {
+        let mut help = setting.help.to_string();
+        if let Some(default) = setting.default.filter(|default| !default.is_empty()) {
+            help.push_str(&format!(" [default: {}]", default));
+        }
+        help.push_str(&format!(
+            " [config: {}.{}] [env: {}]",
+            setting.section, setting.key, setting.env
+        ));
+        lines.push((
+            format!("--{} {}", setting.key.replace('_', "-"), setting.value),
+            help,
+        ));
+    }
+    lines.push(("-h, --help".to_string(), "Show this help".to_string()));
+
+    let width = lines
+        .iter()
+        .map(|(option, _)| option.len())
+        .max()
+        .unwrap_or(0);
+    for (option, help) in lines {
+        text.push_str(&format!("  {:width$}  {}\n", option, help, width = width));
+    }
+    text
+}
@@ -0,0 +1,338 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+use crate::chip8::{Flow, Instruction};
+use crate::constants;
+
+///
+/// Edge Kind
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    Taken,
+    NotTaken,
+    Call,
+}
+
+impl EdgeKind {
+    fn label(&self) -> &'static str {
+        match self {
+            EdgeKind::Taken => "taken",
+            EdgeKind::NotTaken => "not-taken",
+            EdgeKind::Call => "call",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub target: u16,
+    pub kind: EdgeKind,
+}
+
+///
+/// Basic Block
+///
+#[derive(Debug)]
+pub struct BasicBlock {
+    pub start: u16,
+    pub instructions: Vec<(u16, Instruction)>,
+    pub edges: Vec<Edge>,
+    /// Ends in a `Bnnn` computed jump whose targets weren't resolved from a trace
+    pub unresolved: bool,
+}
+
+///
+/// Function
+///
+/// A subroutine: the blocks reachable from its entry point without following calls, and the subroutines it calls.
+///
+#[derive(Debug, Default)]
+pub struct Function {
+    pub blocks: BTreeSet<u16>,
+    pub callees: BTreeSet<u16>,
+}
+
+///
+/// Control Flow Graph
+///
+/// Statically discovers the code reachable from the program start address. Computed jumps can optionally be
+/// resolved from an execution trace: every address executed right after a `Bnnn` becomes one of its targets.
+///
+#[derive(Debug)]
+pub struct ControlFlowGraph {
+    pub blocks: BTreeMap<u16, BasicBlock>,
+    /// Subroutines by entry point, with the program start as the root
+    pub functions: BTreeMap<u16, Function>,
+    rom_end: u16,
+}
+
+impl ControlFlowGraph {
+    pub fn analyze(rom: &[u8], trace: Option<&[u16]>) -> Self {
+        let mut memory = vec![0; constants::SYSTEM_RAM as usize];
+        let rom_start = constants::PROGRAM_START_ADDR as usize;
+        let rom_len = rom.len().min(memory.len() - rom_start);
+        memory[rom_start..rom_start + rom_len].copy_from_slice(&rom[..rom_len]);
+        let rom_end = (rom_start + rom_len) as u16;
+
+        let fetch = |address: u16| {
+            Instruction::decode(
+                (memory[address as usize] as u16) << 8 | memory[address as usize + 1] as u16,
+            )
+        };
+        let in_rom = |address: u16| {
+            address >= constants::PROGRAM_START_ADDR
+                && address < rom_end
+                && address < constants::SYSTEM_RAM - 1
+        };
+
+        let resolved = trace.map_or_else(HashMap::new, |trace| {
+            let mut resolved: HashMap<u16, BTreeSet<u16>> = HashMap::new();
+            for pair in trace.windows(2) {
+                if in_rom(pair[0]) && matches!(fetch(pair[0]), Instruction::JpV0(_)) {
+                    resolved.entry(pair[0]).or_default().insert(pair[1]);
+                }
+            }
+            resolved
+        });
+
+        // Find every reachable instruction and the addresses that start a block
+        let mut leaders = BTreeSet::from([constants::PROGRAM_START_ADDR]);
+        let mut reachable = BTreeSet::new();
+        let mut worklist = vec![constants::PROGRAM_START_ADDR];
+
+        while let Some(address) = worklist.pop() {
+            if !in_rom(address) || !reachable.insert(address) {
+                continue;
+            }
+
+            match fetch(address).flow(address) {
+                Flow::Next(next) => worklist.push(next),
+                Flow::Jump(target) => {
+                    leaders.insert(target);
+                    worklist.push(target);
+                }
+                Flow::Call(target, next) | Flow::Skip(target, next) => {
+                    leaders.extend([target, next]);
+                    worklist.extend([target, next]);
+                }
+                Flow::ComputedJump(_) => {
+                    for &target in resolved.get(&address).into_iter().flatten() {
+                        leaders.insert(target);
+                        worklist.push(target);
+                    }
+                }
+                Flow::Return | Flow::Halt => {}
+            }
+        }
+
+        // Split the reachable code into blocks
+        let mut blocks = BTreeMap::new();
+        for &start in leaders.iter().filter(|address| reachable.contains(address)) {
+            let mut block = BasicBlock {
+                start,
+                instructions: Vec::new(),
+                edges: Vec::new(),
+                unresolved: false,
+            };
+
+            let mut address = start;
+            loop {
+                let instruction = fetch(address);
+                block.instructions.push((address, instruction));
+
+                let edge = |target, kind| Edge { target, kind };
+                match instruction.flow(address) {
+                    Flow::Next(next) => {
+                        if leaders.contains(&next) || !reachable.contains(&next) {
+                            block.edges.push(edge(next, EdgeKind::NotTaken));
+                            break;
+                        }
+                        address = next;
+                        continue;
+                    }
+                    Flow::Jump(target) => block.edges.push(edge(target, EdgeKind::Taken)),
+                    Flow::Call(target, next) => {
+                        block.edges.push(edge(target, EdgeKind::Call));
+                        block.edges.push(edge(next, EdgeKind::NotTaken));
+                    }
+                    Flow::Skip(taken, not_taken) => {
+                        block.edges.push(edge(taken, EdgeKind::Taken));
+                        block.edges.push(edge(not_taken, EdgeKind::NotTaken));
+                    }
+                    Flow::ComputedJump(_) => match resolved.get(&address) {
+                        Some(targets) => block
+                            .edges
+                            .extend(targets.iter().map(|&target| edge(target, EdgeKind::Taken))),
+                        None => block.unresolved = true,
+                    },
+                    Flow::Return | Flow::Halt => {}
+                }
+                break;
+            }
+
+            blocks.insert(start, block);
+        }
+
+        let mut cfg = Self {
+            blocks,
+            functions: BTreeMap::new(),
+            rom_end,
+        };
+        cfg.functions = cfg.find_functions();
+        cfg
+    }
+
+    ///
+    /// Groups blocks into subroutines by following jumps and skips from each call target
+    ///
+    fn find_functions(&self) -> BTreeMap<u16, Function> {
+        let mut entries = BTreeSet::from([constants::PROGRAM_START_ADDR]);
+        for block in self.blocks.values() {
+            entries.extend(
+                block
+                    .edges
+                    .iter()
+                    .filter(|edge| edge.kind == EdgeKind::Call)
+                    .map(|edge| edge.target),
+            );
+        }
+
+        let mut functions = BTreeMap::new();
+        for &entry in &entries {
+            let mut function = Function::default();
+            let mut worklist = vec![entry];
+
+            while let Some(start) = worklist.pop() {
+                let Some(block) = self.blocks.get(&start) else {
+                    continue;
+                };
+                if !function.blocks.insert(start) {
+                    continue;
+                }
+                for edge in &block.edges {
+                    match edge.kind {
+                        EdgeKind::Call => {
+                            function.callees.insert(edge.target);
+                        }
+                        _ if entries.contains(&edge.target) => {}
+                        _ => worklist.push(edge.target),
+                    }
+                }
+            }
+
+            functions.insert(entry, function);
+        }
+
+        functions
+    }
+
+    ///
+    /// Returns the control flow graph in Graphviz DOT format
+    ///
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph cfg {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+
+        for block in self.blocks.values() {
+            let mut label = format!("0x{:03X}:\\l", block.start);
+            for (address, instruction) in &block.instructions {
+                write!(label, "  {:03X}  {}\\l", address, instruction).unwrap();
+            }
+            if block.unresolved {
+                label.push_str("  (unresolved computed jump)\\l");
+            }
+
+            write!(dot, "    {} [label=\"{}\"", node_id(block.start), label).unwrap();
+            if block.unresolved {
+                write!(dot, ", color=red").unwrap();
+            }
+            writeln!(dot, "];").unwrap();
+        }
+
+        let mut outside = BTreeSet::new();
+        for block in self.blocks.values() {
+            for edge in &block.edges {
+                if !self.blocks.contains_key(&edge.target) {
+                    outside.insert(edge.target);
+                }
+                writeln!(
+                    dot,
+                    "    {} -> {} [label=\"{}\"{}];",
+                    node_id(block.start),
+                    node_id(edge.target),
+                    edge.kind.label(),
+                    if edge.kind == EdgeKind::Call {
+                        ", style=dashed"
+                    } else {
+                        ""
+                    }
+                )
+                .unwrap();
+            }
+        }
+
+        for target in outside {
+            writeln!(
+                dot,
+                "    {} [label=\"0x{:03X}\\n(outside ROM, ends at 0x{:03X})\", style=dotted];",
+                node_id(target),
+                target,
+                self.rom_end
+            )
+            .unwrap();
+        }
+
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+
+    ///
+    /// Returns the call graph in Graphviz DOT format
+    ///
+    pub fn call_graph_to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph calls {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+
+        for (&entry, function) in &self.functions {
+            let unresolved = function
+                .blocks
+                .iter()
+                .any(|start| self.blocks[start].unresolved);
+            writeln!(
+                dot,
+                "    {} [label=\"{}0x{:03X}\"{}];",
+                node_id(entry),
+                if entry == constants::PROGRAM_START_ADDR {
+                    "main\\n"
+                } else {
+                    ""
+                },
+                entry,
+                if unresolved { ", color=red" } else { "" }
+            )
+            .unwrap();
+        }
+
+        for (entry, function) in &self.functions {
+            for callee in &function.callees {
+                writeln!(
+                    dot,
+                    "    {} -> {} [label=\"call\"];",
+                    node_id(*entry),
+                    node_id(*callee)
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+fn node_id(address: u16) -> String {
+    format!("\"0x{:03X}\"", address)
+}
@@ -0,0 +1,314 @@
+mod cfg;
+mod recompiler;
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use cfg::ControlFlowGraph;
+
+use crate::chip8::read_trace;
+use crate::platform::{Tape, WavWriter};
+use crate::script::Script;
+use crate::settings::Settings;
+use crate::{constants, utils};
+
+/// Ticks run between checks of the benchmark clock
+const BENCH_BATCH_TICKS: usize = 100_000;
+
+///
+/// Returns true if the argument names a tool instead of a ROM
+///
+pub fn is_command(arg: &str) -> bool {
+    matches!(arg, "audio" | "bench" | "cfg" | "recompile" | "script")
+}
+
+///
+/// Runs a tool. `args` starts with the command name.
+///
+pub fn run(args: &[String]) -> Result<(), String> {
+    match args[0].as_str() {
+        "audio" => render_audio(&args[1..]),
+        "bench" => bench(&args[1..]),
+        "cfg" => export_cfg(&args[1..]),
+        "recompile" => recompile(&args[1..]),
+        "script" => run_script(&args[1..]),
+        command => Err(format!("Unknown command `{}`", command)),
+    }
+}
+
+///
+/// cfg ROM_FILENAME [--out DIR] [--trace TRACE_FILE]
+///
+/// Writes the control flow graph and call graph of a ROM as Graphviz DOT files.
+///
+fn export_cfg(args: &[String]) -> Result<(), String> {
+    let usage = "Usage: cfg ROM_FILENAME [--out DIR] [--trace TRACE_FILE]";
+
+    let mut rom_filename = None;
+    let mut out_dir = ".".to_string();
+    let mut trace_filename = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => out_dir = args.next().ok_or(usage)?.clone(),
+            "--trace" => trace_filename = Some(args.next().ok_or(usage)?.clone()),
+            _ if rom_filename.is_none() => rom_filename = Some(arg.clone()),
+            _ => return Err(usage.to_string()),
+        }
+    }
+    let rom_filename = rom_filename.ok_or(usage)?;
+
+    let rom = read_rom(&Settings::for_rom(&rom_filename)?)?;
+    let trace = trace_filename
+        .map(|filename| read_trace(&filename))
+        .transpose()?;
+
+    let cfg = ControlFlowGraph::analyze(&rom, trace.as_deref());
+
+    let stem = utils::rom_name(&rom_filename);
+    write_file(&out_dir, &format!("{}.cfg.dot", stem), &cfg.to_dot())?;
+    write_file(
+        &out_dir,
+        &format!("{}.calls.dot", stem),
+        &cfg.call_graph_to_dot(),
+    )?;
+
+    let unresolved = cfg.blocks.values().filter(|block| block.unresolved).count();
+    println!(
+        "{} blocks, {} subroutines, {} unresolved computed jumps",
+        cfg.blocks.len(),
+        cfg.functions.len() - 1,
+        unresolved
+    );
+
+    Ok(())
+}
+
+///
+/// recompile ROM_FILENAME [--out FILE] [--trace TRACE_FILE]
+///
+/// Translates a ROM into a Rust module, `src/recompiled.rs` by default, that the emulator runs instead of
+/// interpreting the ROM when it's built with the `recompiled` feature.
+///
+fn recompile(args: &[String]) -> Result<(), String> {
+    let usage = "Usage: recompile ROM_FILENAME [--out FILE] [--trace TRACE_FILE]";
+
+    let mut rom_filename = None;
+    let mut out_filename = "src/recompiled.rs".to_string();
+    let mut trace_filename = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => out_filename = args.next().ok_or(usage)?.clone(),
+            "--trace" => trace_filename = Some(args.next().ok_or(usage)?.clone()),
+            _ if rom_filename.is_none() => rom_filename = Some(arg.clone()),
+            _ => return Err(usage.to_string()),
+        }
+    }
+    let rom_filename = rom_filename.ok_or(usage)?;
+
+    let rom = read_rom(&Settings::for_rom(&rom_filename)?)?;
+    let trace = trace_filename
+        .map(|filename| read_trace(&filename))
+        .transpose()?;
+
+    let cfg = ControlFlowGraph::analyze(&rom, trace.as_deref());
+    let source = recompiler::recompile(&rom, &cfg, &rom_filename);
+
+    fs::write(&out_filename, source)
+        .map_err(|e| format!("Couldn't write `{}`: {}", out_filename, e))?;
+    println!(
+        "Wrote {} ({} blocks). Build with `--features recompiled` to use it.",
+        out_filename,
+        cfg.blocks.len()
+    );
+
+    Ok(())
+}
+
+///
+/// bench ROM_FILENAME [--seconds N] [--no-cache]
+///
+/// Runs a ROM headless as fast as possible and reports the number of instructions executed per second. Whenever
+/// the ROM waits for a key, the keys are pressed in turn so it keeps running.
+///
+fn bench(args: &[String]) -> Result<(), String> {
+    let usage = "Usage: bench ROM_FILENAME [--seconds N] [--no-cache]";
+
+    let mut rom_filename = None;
+    let mut seconds = 5.0;
+    let mut use_cache = true;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seconds" => {
+                seconds = args
+                    .next()
+                    .and_then(|value| value.parse::<f64>().ok())
+                    .filter(|value| *value > 0.0)
+                    .ok_or(usage)?
+            }
+            "--no-cache" => use_cache = false,
+            _ if rom_filename.is_none() => rom_filename = Some(arg.clone()),
+            _ => return Err(usage.to_string()),
+        }
+    }
+    let rom_filename = rom_filename.ok_or(usage)?;
+
+    let settings = Settings::for_rom(&rom_filename)?;
+    let mut cpu = settings.cpu()?;
+    cpu.load_program(read_rom(&settings)?);
+    #[cfg(feature = "recompiled")]
+    {
+        cpu.recompiled = Some(crate::recompiled::run_block);
+    }
+
+    let duration = Duration::from_secs_f64(seconds);
+    let start = Instant::now();
+    let mut executed: u64 = 0;
+    let mut next_key = 0;
+
+    while start.elapsed() < duration && !cpu.quit_flag {
+        if use_cache {
+            executed += cpu.run(BENCH_BATCH_TICKS) as u64;
+        } else {
+            for _ in 0..BENCH_BATCH_TICKS {
+                if !cpu.is_waiting_for_key() {
+                    executed += 1;
+                }
+                cpu.tick();
+            }
+        }
+        // Batches stand in for frames, so delay loops still end
+        cpu.tick_timers();
+
+        // Tap the next key, which completes Fx0A whether it waits for the press or the release
+        if cpu.is_waiting_for_key() {
+            cpu.keys.set(next_key, true);
+            cpu.keys.set(next_key, false);
+            next_key = (next_key + 1) % 16;
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "{}: {} instructions in {:.2}s ({:.1}M instructions/s, {})",
+        rom_filename,
+        executed,
+        elapsed,
+        executed as f64 / elapsed / 1_000_000.0,
+        if use_cache {
+            "block cache"
+        } else {
+            "interpreter"
+        }
+    );
+
+    Ok(())
+}
+
+///
+/// audio ROM_FILENAME [--seconds N] [--out FILE]
+///
+/// Runs a ROM headless for a number of seconds and writes the beeps it plays to a WAV file. Each 60 Hz frame
+/// renders the samples for the frame from the sound timer, so the output only depends on the ROM and the tone
+/// settings, which makes it usable for audio regression tests.
+///
+fn render_audio(args: &[String]) -> Result<(), String> {
+    let usage = "Usage: audio ROM_FILENAME [--seconds N] [--out FILE]";
+
+    let mut rom_filename = None;
+    let mut seconds = 10.0;
+    let mut out_filename = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seconds" => {
+                seconds = args
+                    .next()
+                    .and_then(|value| value.parse::<f64>().ok())
+                    .filter(|value| *value > 0.0)
+                    .ok_or(usage)?
+            }
+            "--out" => out_filename = Some(args.next().ok_or(usage)?.clone()),
+            _ if rom_filename.is_none() => rom_filename = Some(arg.clone()),
+            _ => return Err(usage.to_string()),
+        }
+    }
+    let rom_filename = rom_filename.ok_or(usage)?;
+    let out_filename = out_filename.unwrap_or(format!("{}.wav", utils::rom_name(&rom_filename)));
+
+    let settings = Settings::for_rom(&rom_filename)?;
+    let mut cpu = settings.cpu()?;
+    cpu.load_program(read_rom(&settings)?);
+
+    let mut synth = settings.tone()?.synth(constants::SAMPLE_RATE);
+    let mut wav = WavWriter::create(&out_filename, constants::SAMPLE_RATE)?;
+    let mut samples = vec![0.0; constants::SAMPLE_RATE as usize / constants::FRAME_RATE];
+    let mut frame_clock = settings.frame_clock()?;
+
+    let frames = (seconds * constants::FRAME_RATE as f64).round() as usize;
+    for _ in 0..frames {
+        if cpu.quit_flag {
+            break;
+        }
+        cpu.run(frame_clock.next_frame());
+        cpu.tick_timers();
+        synth.gate = cpu.sound_timer > 0;
+        synth.render(&mut samples);
+        wav.write(&samples);
+    }
+
+    let path = wav.finish()?;
+    println!("Wrote {}", path);
+    Ok(())
+}
+
+///
+/// script SCRIPT_FILE ROM_FILENAME
+///
+/// Runs a ROM headless, driving the keypad with a script as fast as possible. Fails if any of the script's
+/// assertions failed, so scripts can be used as end-to-end tests.
+///
+fn run_script(args: &[String]) -> Result<(), String> {
+    let usage = "Usage: script SCRIPT_FILE ROM_FILENAME";
+    let [script_filename, rom_filename] = args else {
+        return Err(usage.to_string());
+    };
+
+    let mut script = Script::load(script_filename)?;
+    let settings = Settings::for_rom(rom_filename)?;
+    let mut cpu = settings.cpu()?;
+    cpu.load_program(read_rom(&settings)?);
+
+    script.run_headless(&mut cpu, settings.frame_clock()?);
+
+    if script.report() {
+        Ok(())
+    } else {
+        Err(format!("{}: script failed", script_filename))
+    }
+}
+
+///
+/// Reads the ROM named in the settings, looking it up in the ROM folder they give
+///
+fn read_rom(settings: &Settings) -> Result<Vec<u8>, String> {
+    let mut tape = Tape::new();
+    tape.folder = settings.get("rom_folder").unwrap_or_default().to_string();
+    tape.read(&settings.rom, settings.cpu()?.max_program_size())?;
+    Ok(tape.rom)
+}
+
+fn write_file(dir: &str, filename: &str, contents: &str) -> Result<(), String> {
+    let path = Path::new(dir).join(filename);
+    fs::write(&path, contents)
+        .map_err(|e| format!("Couldn't write `{}`: {}", path.display(), e))?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
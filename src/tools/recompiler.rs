@@ -0,0 +1,186 @@
+use std::fmt::Write;
+
+use super::cfg::{BasicBlock, ControlFlowGraph};
+use crate::chip8::Instruction;
+use crate::constants;
+
+///
+/// Translates the blocks of a control flow graph into a Rust module, which also carries the ROM so a build
+/// with the module plays the game without reading it from disk
+///
+/// Every block becomes a function that runs its instructions directly on a `Cpu`, so there's no fetching,
+/// decoding or dispatching left at runtime. A block only runs if it fits in the remaining ticks and RAM still
+/// holds the code it was compiled from. Otherwise, as for addresses only reachable through computed jumps, it
+/// returns 0 and the interpreter executes the instruction instead.
+///
+pub fn recompile(rom: &[u8], cfg: &ControlFlowGraph, rom_filename: &str) -> String {
+    let mut source = String::new();
+    writeln!(
+        source,
+        "// Generated by `rust-chip8 recompile {}`. Do not edit.",
+        rom_filename
+    )
+    .unwrap();
+    writeln!(source).unwrap();
+    writeln!(source, "use crate::chip8::Cpu;").unwrap();
+    writeln!(source).unwrap();
+    let blocks: Vec<&BasicBlock> = cfg
+        .blocks
+        .values()
+        .filter(|block| {
+            block_end(block) as usize <= constants::PROGRAM_START_ADDR as usize + rom.len()
+        })
+        .collect();
+
+    // The emulator plays the ROM it was compiled from, and blocks check RAM against it
+    writeln!(source, "pub const ROM_NAME: &str = {:?};", rom_filename).unwrap();
+    writeln!(source, "pub const ROM: &[u8] = &[").unwrap();
+    for chunk in rom.chunks(16) {
+        let bytes: Vec<String> = chunk
+            .iter()
+            .map(|byte| format!("0x{:02X},", byte))
+            .collect();
+        writeln!(source, "    {}", bytes.join(" ")).unwrap();
+    }
+    writeln!(source, "];").unwrap();
+    writeln!(source).unwrap();
+
+    writeln!(
+        source,
+        "pub fn run_block(cpu: &mut Cpu, max_ticks: usize) -> usize {{"
+    )
+    .unwrap();
+    writeln!(source, "    match cpu.get_program_counter() {{").unwrap();
+    for block in &blocks {
+        writeln!(
+            source,
+            "        0x{:03X} => block_{:03x}(cpu, max_ticks),",
+            block.start, block.start
+        )
+        .unwrap();
+    }
+    writeln!(source, "        _ => 0,").unwrap();
+    writeln!(source, "    }}").unwrap();
+    writeln!(source, "}}").unwrap();
+
+    for block in blocks {
+        write_block(&mut source, block);
+    }
+
+    source
+}
+
+fn write_block(source: &mut String, block: &BasicBlock) {
+    let len = block.instructions.len();
+    let code = format!(
+        "&ROM[0x{:03X}..0x{:03X}]",
+        block.start - constants::PROGRAM_START_ADDR,
+        block_end(block) - constants::PROGRAM_START_ADDR
+    );
+
+    writeln!(source).unwrap();
+    writeln!(
+        source,
+        "fn block_{:03x}(cpu: &mut Cpu, max_ticks: usize) -> usize {{",
+        block.start
+    )
+    .unwrap();
+    writeln!(
+        source,
+        "    if max_ticks < {} || !cpu.code_matches(0x{:03X}, {}) {{",
+        len, block.start, code
+    )
+    .unwrap();
+    writeln!(source, "        return 0;").unwrap();
+    writeln!(source, "    }}").unwrap();
+
+    for (count, (address, instruction)) in block.instructions.iter().enumerate() {
+        writeln!(source, "    // {:03X}: {}", address, instruction).unwrap();
+        writeln!(source, "    cpu.begin_instruction();").unwrap();
+        writeln!(source, "    cpu.{};", method_call(instruction)).unwrap();
+
+        if count + 1 == len {
+            continue;
+        }
+        match instruction {
+            Instruction::LdVxK(_) => {
+                writeln!(source, "    if cpu.is_waiting_for_key() {{").unwrap();
+                writeln!(source, "        return {};", count + 1).unwrap();
+                writeln!(source, "    }}").unwrap();
+            }
+            Instruction::LdBVx(_) | Instruction::LdIVx(_) => {
+                writeln!(
+                    source,
+                    "    if !cpu.code_matches(0x{:03X}, {}) {{",
+                    block.start, code
+                )
+                .unwrap();
+                writeln!(source, "        return {};", count + 1).unwrap();
+                writeln!(source, "    }}").unwrap();
+            }
+            _ => {}
+        }
+    }
+
+    writeln!(source, "    {}", len).unwrap();
+    writeln!(source, "}}").unwrap();
+}
+
+fn block_end(block: &BasicBlock) -> u16 {
+    block
+        .instructions
+        .last()
+        .map_or(block.start, |(address, _)| address + constants::OPCODE_SIZE)
+}
+
+///
+/// Returns the call to the `Cpu` method that executes the instruction
+///
+fn method_call(instruction: &Instruction) -> String {
+    match *instruction {
+        Instruction::Scd(n) => format!("scd({})", n),
+        Instruction::Cls => "cls()".to_string(),
+        Instruction::Ret => "ret()".to_string(),
+        Instruction::Scr => "scr()".to_string(),
+        Instruction::Scl => "scl()".to_string(),
+        Instruction::Exit => "exit()".to_string(),
+        Instruction::Low => "low()".to_string(),
+        Instruction::High => "high()".to_string(),
+        Instruction::Jp(nnn) => format!("jp(0x{:03X})", nnn),
+        Instruction::Call(nnn) => format!("call(0x{:03X})", nnn),
+        Instruction::SeVx(x, kk) => format!("se_vx(0x{:X}, 0x{:02X})", x, kk),
+        Instruction::SneVx(x, kk) => format!("sne_vx(0x{:X}, 0x{:02X})", x, kk),
+        Instruction::SeVxVy(x, y) => format!("se_vx_vy(0x{:X}, 0x{:X})", x, y),
+        Instruction::LdVx(x, kk) => format!("ld_vx(0x{:X}, 0x{:02X})", x, kk),
+        Instruction::AddVx(x, kk) => format!("add_vx(0x{:X}, 0x{:02X})", x, kk),
+        Instruction::LdVxVy(x, y) => format!("ld_vx_vy(0x{:X}, 0x{:X})", x, y),
+        Instruction::OrVxVy(x, y) => format!("or_vx_vy(0x{:X}, 0x{:X})", x, y),
+        Instruction::AndVxVy(x, y) => format!("and_vx_vy(0x{:X}, 0x{:X})", x, y),
+        Instruction::XorVxVy(x, y) => format!("xor_vx_vy(0x{:X}, 0x{:X})", x, y),
+        Instruction::AddVxVy(x, y) => format!("add_vx_vy(0x{:X}, 0x{:X})", x, y),
+        Instruction::SubVxVy(x, y) => format!("sub_vx_vy(0x{:X}, 0x{:X})", x, y),
+        Instruction::ShrVxVy(x, y) => format!("shr_vx_vy(0x{:X}, 0x{:X})", x, y),
+        Instruction::SubnVxVy(x, y) => format!("subn_vx_vy(0x{:X}, 0x{:X})", x, y),
+        Instruction::ShlVxVy(x, y) => format!("shl_vx_vy(0x{:X}, 0x{:X})", x, y),
+        Instruction::SneVxVy(x, y) => format!("sne_vx_vy(0x{:X}, 0x{:X})", x, y),
+        Instruction::LdI(nnn) => format!("ld_i(0x{:03X})", nnn),
+        Instruction::JpV0(nnn) => format!("jp_v0(0x{:03X})", nnn),
+        Instruction::RndVx(x, kk) => format!("rnd_vx(0x{:X}, 0x{:02X})", x, kk),
+        Instruction::DrwVxVy(x, y, n) => format!("drw_vx_vy(0x{:X}, 0x{:X}, {})", x, y, n),
+        Instruction::SkpVx(x) => format!("skp_vx(0x{:X})", x),
+        Instruction::SknpVx(x) => format!("sknp_vx(0x{:X})", x),
+        Instruction::LdVxDt(x) => format!("ld_vx_dt(0x{:X})", x),
+        Instruction::LdVxK(x) => format!("ld_vx_k(0x{:X})", x),
+        Instruction::LdDtVx(x) => format!("ld_dt_vx(0x{:X})", x),
+        Instruction::LdStVx(x) => format!("ld_st_vx(0x{:X})", x),
+        Instruction::AddIVx(x) => format!("add_i_vx(0x{:X})", x),
+        Instruction::LdFVx(x) => format!("ld_f_vx(0x{:X})", x),
+        Instruction::LdHfVx(x) => format!("ld_hf_vx(0x{:X})", x),
+        Instruction::LdBVx(x) => format!("ld_b_vx(0x{:X})", x),
+        Instruction::LdIVx(x) => format!("ld_i_vx(0x{:X})", x),
+        Instruction::LdVxI(x) => format!("ld_vx_i(0x{:X})", x),
+        Instruction::LdRVx(x) => format!("ld_r_vx(0x{:X})", x),
+        Instruction::LdVxR(x) => format!("ld_vx_r(0x{:X})", x),
+        Instruction::Unknown(word) => format!("no_op(0x{:04X})", word),
+    }
+}
@@ -1,22 +1,54 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{convert::TryInto, fmt::Debug};
 
+#[allow(dead_code)]
+pub fn vec_to_array<T: Debug, const N: usize>(vec: Vec<T>) -> [T; N] {
+    vec.try_into().unwrap()
+}
+
 ///
-/// Returns an individual bit from a given byte
+/// Returns the name of a ROM, its file name without the folder or extension, or `stdin` for `-`
 ///
-#[inline]
-pub fn read_bit_from_byte(byte: &u8, bit_position: u8) -> &u8 {
-    if bit_position < 8 {
-        if byte & (1 << bit_position) != 0 {
-            &1
-        } else {
-            &0
-        }
-    } else {
-        &0
+pub fn rom_name(filename: &str) -> String {
+    match filename {
+        "-" => "stdin".to_string(),
+        _ => Path::new(filename)
+            .file_stem()
+            .map_or(filename.to_string(), |stem| {
+                stem.to_string_lossy().to_string()
+            }),
     }
 }
 
-#[allow(dead_code)]
-pub fn vec_to_array<T: Debug, const N: usize>(vec: Vec<T>) -> [T; N] {
-    vec.try_into().unwrap()
+///
+/// Returns the current UTC time as `YYYYMMDD-HHMMSS`, for naming files
+///
+pub fn timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let (days, time) = (seconds / 86_400, seconds % 86_400);
+
+    // Converts days since 1970-01-01 to a civil date (Howard Hinnant's `civil_from_days`)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60
+    )
 }